@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use image::{GrayImage, Luma, Rgb, RgbaImage, RgbImage};
+use tokio::task;
+
+/// preprocessing applied to both images before they get compared
+#[derive(Clone, Copy, Default)]
+pub struct CompareOptions<'a> {
+    /// black pixels in the mask are ignored
+    pub mask: Option<&'a GrayImage>,
+    /// gaussian blur sigma to smooth out rendering jitter, 0 to disable
+    pub blur: f32,
+    /// when the sizes differ, only compare the overlapping top left region instead of scoring 0
+    pub crop_to_common: bool,
+    /// compare luminance only, so color rendering differences don't count
+    pub grayscale: bool,
+    /// composite transparent pixels onto this color, otherwise they turn black
+    pub transparent_as: Option<[u8; 3]>,
+}
+
+/// the capture couldn't be decoded or compared, so there's no trustworthy score for it
+#[derive(Debug)]
+pub struct CompareError(String);
+
+impl Display for CompareError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "comparison failed -> {}", self.0)
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+pub fn load_and_compare(bytes: &[u8], last_image: &Option<RgbImage>, options: CompareOptions) -> Result<(f64, RgbImage), CompareError> {
+    // compare with a few special stuff
+    task::block_in_place(move || -> Result<(f64, RgbImage), CompareError> {
+        let screenshot_image = load(bytes, options)?;
+        let comparison = compare_images(&screenshot_image, last_image.as_ref(), options)?;
+
+        Ok((comparison, screenshot_image))
+    })
+}
+
+/// like load and compare, but scored against whichever of the references is the closest
+pub fn load_and_compare_best(bytes: &[u8], references: &[RgbImage], options: CompareOptions) -> Result<(f64, RgbImage), CompareError> {
+    task::block_in_place(move || -> Result<(f64, RgbImage), CompareError> {
+        let screenshot_image = load(bytes, options)?;
+
+        let mut best = 0.0;
+        for reference in references {
+            best = compare_images(&screenshot_image, Some(reference), options)?.max(best);
+        }
+
+        Ok((best, screenshot_image))
+    })
+}
+
+fn load(bytes: &[u8], options: CompareOptions) -> Result<RgbImage, CompareError> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| CompareError(e.to_string()))?;
+
+    Ok(match options.transparent_as {
+        Some(color) => flatten(&image.into_rgba8(), color),
+        None => image.into_rgb8(),
+    })
+}
+
+pub fn compare_images(image: &RgbImage, last_image: Option<&RgbImage>, options: CompareOptions) -> Result<f64, CompareError> {
+    let Some(last_image) = last_image else {
+        return Ok(1.0);
+    };
+
+    let mut image = Cow::Borrowed(image);
+    let mut last_image = Cow::Borrowed(last_image);
+
+    if options.crop_to_common && image.dimensions() != last_image.dimensions() {
+        let width = image.width().min(last_image.width());
+        let height = image.height().min(last_image.height());
+
+        image = Cow::Owned(image::imageops::crop_imm(image.as_ref(), 0, 0, width, height).to_image());
+        last_image = Cow::Owned(image::imageops::crop_imm(last_image.as_ref(), 0, 0, width, height).to_image());
+    }
+
+    if options.blur > 0.0 {
+        image = Cow::Owned(image::imageops::blur(image.as_ref(), options.blur));
+        last_image = Cow::Owned(image::imageops::blur(last_image.as_ref(), options.blur));
+    }
+
+    if let Some(mask) = options.mask {
+        if mask.dimensions() == image.dimensions() && mask.dimensions() == last_image.dimensions() {
+            image = Cow::Owned(apply_mask(&image, mask));
+            last_image = Cow::Owned(apply_mask(&last_image, mask));
+        } else {
+            eprintln!("mask is {:?} but captures are {:?} & {:?}, skipping mask", mask.dimensions(), image.dimensions(), last_image.dimensions());
+        }
+    }
+
+    if options.grayscale {
+        return gray_score(&image, &last_image);
+    }
+
+    hybrid_score(&image, &last_image)
+}
+
+fn gray_score(image: &RgbImage, last_image: &RgbImage) -> Result<f64, CompareError> {
+    // same as the hybrid compare, different sizes means the site was 100% updated
+    if image.dimensions() != last_image.dimensions() {
+        return Ok(0.0);
+    }
+
+    let image = image::imageops::grayscale(image);
+    let last_image = image::imageops::grayscale(last_image);
+
+    image_compare::gray_similarity_structure(&image_compare::Algorithm::MSSIMSimple, &image, &last_image)
+        .map(|r| r.score)
+        .map_err(|e| CompareError(e.to_string()))
+}
+
+fn hybrid_score(image: &RgbImage, last_image: &RgbImage) -> Result<f64, CompareError> {
+    // if the image sizes are different, that means the site was 100% updated
+    if image.dimensions() != last_image.dimensions() {
+        return Ok(0.0);
+    }
+
+    image_compare::rgb_hybrid_compare(image, last_image)
+        .map(|r| r.score)
+        .map_err(|e| CompareError(e.to_string()))
+}
+
+/// blends every pixel onto the background color by its alpha
+fn flatten(image: &RgbaImage, background: [u8; 3]) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend = |channel: u8, background: u8| ((channel as u16 * a as u16 + background as u16 * (255 - a as u16)) / 255) as u8;
+
+        Rgb([blend(r, background[0]), blend(g, background[1]), blend(b, background[2])])
+    })
+}
+
+/// black pixels in the mask are ignored by painting them black in the image too
+fn apply_mask(image: &RgbImage, mask: &GrayImage) -> RgbImage {
+    let mut masked = image.clone();
+    for (pixel, mask_pixel) in masked.pixels_mut().zip(mask.pixels()) {
+        if mask_pixel.0[0] < 128 {
+            *pixel = Rgb([0, 0, 0]);
+        }
+    }
+
+    masked
+}
+
+/// per channel difference for a pixel to be highlighted as changed
+const HIGHLIGHT_TOLERANCE: u8 = 24;
+
+/// the new capture dimmed with the pixels that changed since the old one in red, none if the sizes differ
+pub fn highlight_changes(last_image: &RgbImage, image: &RgbImage) -> Option<RgbImage> {
+    if image.dimensions() != last_image.dimensions() {
+        return None;
+    }
+
+    Some(RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let (a, b) = (image.get_pixel(x, y).0, last_image.get_pixel(x, y).0);
+        if a.iter().zip(b).any(|(a, b)| a.abs_diff(b) > HIGHLIGHT_TOLERANCE) {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb(a.map(|channel| channel / 3))
+        }
+    }))
+}
+
+/// side of the square blocks the auto mask is learned in, single pixels would be too noisy
+const MASK_BLOCK: u32 = 16;
+/// average per channel difference for a block to count as changed
+const MASK_BLOCK_TOLERANCE: u64 = 8;
+
+/// counts how often each block of the capture changes between runs, to mask the ones that keep changing
+#[derive(Debug)]
+pub struct MaskLearner {
+    width: u32,
+    height: u32,
+    changes: Vec<u32>,
+    samples: u32,
+}
+
+impl MaskLearner {
+    pub fn new() -> MaskLearner {
+        MaskLearner { width: 0, height: 0, changes: vec![], samples: 0 }
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn observe(&mut self, last_image: &RgbImage, image: &RgbImage) {
+        if image.dimensions() != last_image.dimensions() {
+            return;
+        }
+
+        // starts over when the capture size changes, a mask only fits one size anyway
+        if image.dimensions() != (self.width, self.height) {
+            (self.width, self.height) = image.dimensions();
+            self.changes = vec![0; (self.blocks_wide() * self.blocks_high()) as usize];
+            self.samples = 0;
+        }
+
+        let blocks_wide = self.blocks_wide();
+        for block_y in 0..self.blocks_high() {
+            for block_x in 0..blocks_wide {
+                let (x, y) = (block_x * MASK_BLOCK, block_y * MASK_BLOCK);
+                let (width, height) = (MASK_BLOCK.min(self.width - x), MASK_BLOCK.min(self.height - y));
+
+                let mut difference = 0;
+                for py in y..y + height {
+                    for px in x..x + width {
+                        let (a, b) = (image.get_pixel(px, py).0, last_image.get_pixel(px, py).0);
+                        difference += a.iter().zip(b).map(|(a, b)| a.abs_diff(b) as u64).sum::<u64>();
+                    }
+                }
+
+                if difference > MASK_BLOCK_TOLERANCE * 3 * (width * height) as u64 {
+                    self.changes[(block_y * blocks_wide + block_x) as usize] += 1;
+                }
+            }
+        }
+
+        self.samples += 1;
+    }
+
+    /// blocks that changed in at least half of the samples are black (ignored)
+    pub fn mask(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let changes = self.changes[((y / MASK_BLOCK) * self.blocks_wide() + x / MASK_BLOCK) as usize];
+            if changes * 2 >= self.samples { Luma([0]) } else { Luma([255]) }
+        })
+    }
+
+    fn blocks_wide(&self) -> u32 {
+        self.width.div_ceil(MASK_BLOCK)
+    }
+
+    fn blocks_high(&self) -> u32 {
+        self.height.div_ceil(MASK_BLOCK)
+    }
+}
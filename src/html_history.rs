@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::stats::unix_now_millis;
+
+/// saves the html of a detected change, returns the html saved the time before (if any)
+pub async fn save(dir: &Path, key: &str, html: &str) -> anyhow::Result<Option<String>> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    // files are named by the page's key & padded unix millis, so the biggest name with the key is the latest one
+    let prefix = format!("{key}-");
+    let mut latest = None;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if !name.to_string_lossy().starts_with(&prefix) {
+            continue;
+        }
+
+        if latest.as_ref().is_none_or(|l| name > *l) {
+            latest = Some(name);
+        }
+    }
+
+    let previous = match latest {
+        Some(name) => Some(tokio::fs::read_to_string(dir.join(name)).await?),
+        None => None,
+    };
+
+    tokio::fs::write(dir.join(format!("{prefix}{:013}.html", unix_now_millis())), html).await?;
+    Ok(previous)
+}
+
+/// rough tag level diff, lists the tags/text that were added & removed (order isn't taken into account)
+pub fn diff(old: &str, new: &str, max_chars: usize) -> String {
+    let old_parts = old.split_inclusive('>').map(str::trim).filter(|p| !p.is_empty()).collect::<Vec<&str>>();
+    let new_parts = new.split_inclusive('>').map(str::trim).filter(|p| !p.is_empty()).collect::<Vec<&str>>();
+    let old_set = old_parts.iter().collect::<HashSet<_>>();
+    let new_set = new_parts.iter().collect::<HashSet<_>>();
+
+    let removed = old_parts.iter()
+        .filter(|p| !new_set.contains(p))
+        .map(|p| format!("- {p}"));
+
+    let added = new_parts.iter()
+        .filter(|p| !old_set.contains(p))
+        .map(|p| format!("+ {p}"));
+
+    let mut diff = String::new();
+    for line in removed.chain(added) {
+        if diff.len() + line.len() > max_chars {
+            diff.push_str("...");
+            break;
+        }
+
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+
+    diff
+}
@@ -1,243 +1,1970 @@
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::atomic;
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Context;
+use base64::Engine;
 use chromiumoxide::{Browser, Page};
 use chromiumoxide::browser::BrowserConfigBuilder;
-use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
+use chromiumoxide::cdp::browser_protocol::network::{CookieParam, EventResponseReceived, Headers, ResourceType, SetCacheDisabledParams, SetCookiesParams, SetExtraHttpHeadersParams};
+use chromiumoxide::cdp::browser_protocol::dom::Rgba;
+use chromiumoxide::cdp::browser_protocol::emulation::{ClearDeviceMetricsOverrideParams, SetDefaultBackgroundColorOverrideParams, SetDeviceMetricsOverrideParams, SetTouchEmulationEnabledParams};
+use chromiumoxide::cdp::browser_protocol::page::{CaptureScreenshotFormat, CaptureScreenshotParams, Viewport, EventJavascriptDialogOpening, EventLifecycleEvent, HandleJavaScriptDialogParams, ReloadParams, SetLifecycleEventsEnabledParams};
+use chromiumoxide::cdp::browser_protocol::security::SetIgnoreCertificateErrorsParams;
 use chromiumoxide::page::ScreenshotParams;
+use clap::{Parser, Subcommand};
 use futures::StreamExt;
 use image::RgbImage;
-use once_cell::sync::OnceCell;
-use pushover_rs::{MessageBuilder, send_pushover_request};
+use once_cell::sync::{Lazy, OnceCell};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
 use serde::Deserialize;
 use tokio::task;
 use tokio::time::sleep;
+use url::Url;
 
 use website_data::WebsiteData;
 
-use crate::website_data::WebsiteDataConfig;
+use crate::compare::{CompareError, CompareOptions};
+use crate::notify::{CheckResult, Fanout, Notification, NotifierConfig, QuietHours, QuietHoursConfig, RateLimitConfig, RetryConfig};
+use crate::website_data::{Decision, DeviceMetrics, DialogAction, PresenceConfig, ScriptCondition, WebsiteDataConfig};
 
+mod compare;
+mod html_history;
+mod notify;
+mod snapshot;
+mod stats;
+mod template;
+mod validate;
 mod website_data;
 
-static PUSHOVER_KEYS: OnceCell<(String, String)> = OnceCell::new();
-static MERCH_KEYWORDS: OnceCell<Vec<String>> = OnceCell::new();
+/// config files that are looked for, the first one that exists is used & its extension says how it's parsed
+const CONFIG_NAMES: [&str; 4] = ["sites.toml", "sites.yaml", "sites.yml", "sites.json"];
+/// directory next to the config whose files each add more sites
+const SITES_DIR: &str = "sites.d";
+/// user agent of every site that doesn't set its own
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+/// seconds between two cycles, sites with an interval or schedule may shorten it
+const CYCLE_PAUSE: u64 = 25;
+/// longest pause while every site waits for its interval or schedule, so digests & heartbeats still go out
+const IDLE_PAUSE: u64 = 300;
+/// exit code when every site kept failing for fail fast after cycles, so a supervisor can tell it apart
+const TOTAL_FAILURE_EXIT_CODE: i32 = 3;
+
+/// chromiumoxide's default args without --enable-automation, which sites can see
+const STEALTH_ARGS: [&str; 25] = [
+    "--disable-background-networking",
+    "--enable-features=NetworkService,NetworkServiceInProcess",
+    "--disable-background-timer-throttling",
+    "--disable-backgrounding-occluded-windows",
+    "--disable-breakpad",
+    "--disable-client-side-phishing-detection",
+    "--disable-component-extensions-with-background-pages",
+    "--disable-default-apps",
+    "--disable-dev-shm-usage",
+    "--disable-extensions",
+    "--disable-features=TranslateUI",
+    "--disable-hang-monitor",
+    "--disable-ipc-flooding-protection",
+    "--disable-popup-blocking",
+    "--disable-prompt-on-repost",
+    "--disable-renderer-backgrounding",
+    "--disable-sync",
+    "--force-color-profile=srgb",
+    "--metrics-recording-only",
+    "--no-first-run",
+    "--password-store=basic",
+    "--use-mock-keychain",
+    "--enable-blink-features=IdleDetection",
+    "--lang=en_US",
+    "--disable-blink-features=AutomationControlled",
+];
+
+/// every notification backend, sites pick which of them they go through
+static NOTIFIERS: OnceCell<Fanout> = OnceCell::new();
+static SILENCED: AtomicBool = AtomicBool::new(false);
+/// whether dialogs get accepted instead of dismissed, follows the site being navigated
+static ACCEPT_DIALOGS: AtomicBool = AtomicBool::new(false);
+static MIN_NAVIGATION_INTERVAL: OnceCell<u64> = OnceCell::new();
+static IGNORE_CERT_ERRORS: OnceCell<bool> = OnceCell::new();
+static GRAYSCALE_COMPARE: OnceCell<bool> = OnceCell::new();
+/// changes found this cycle, only set in digest mode or with coordinated change detection
+static DIGEST: OnceCell<Mutex<Vec<DigestEntry>>> = OnceCell::new();
+static QUIET_HOURS: OnceCell<QuietHours> = OnceCell::new();
+/// notifications held back during the quiet hours, sent as a digest once they're over
+static QUIET_QUEUE: Lazy<Mutex<Vec<DigestEntry>>> = Lazy::new(Default::default);
+/// when each origin was last navigated to (or is reserved to be)
+static LAST_NAVIGATIONS: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+
+#[derive(Parser)]
+#[command(about = "Send a notification when a website visibly changes")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// config file, the first of sites.toml/yaml/yml/json that exists if not set
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// directory to run in, relative paths (including the ones in the config) are resolved against it
+    #[arg(long, value_name = "DIR")]
+    working_dir: Option<PathBuf>,
+    /// env file to load instead of .env
+    #[arg(long, value_name = "PATH", conflicts_with = "no_dotenv")]
+    env_file: Option<PathBuf>,
+    /// don't load any env file, everything comes from the environment
+    #[arg(long)]
+    no_dotenv: bool,
+    /// print the stats of every site & exit
+    #[arg(long)]
+    stats: bool,
+    /// print where the cooldown of this site is at & exit
+    #[arg(long, value_name = "URL")]
+    explain: Option<String>,
+    /// send a test notification through every notifier & exit
+    #[arg(long)]
+    test_notifications: bool,
+    /// bundle the config & state directory into a tar & exit
+    #[arg(long, value_name = "PATH")]
+    export_snapshot: Option<PathBuf>,
+    /// restore the config & state directory from a tar & exit
+    #[arg(long, value_name = "PATH", conflicts_with = "export_snapshot")]
+    import_snapshot: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// check the config for problems without launching the browser, exits with 1 if there are any
+    Validate,
+}
+
+struct DigestEntry {
+    title: String,
+    site: String,
+    notifiers: Vec<String>,
+    url: String,
+    url_title: Option<String>,
+    priority: i8,
+    message: String,
+    score: Option<f64>,
+    merch: bool,
+    sound: Option<String>,
+    /// the captures are kept so a held back notification still has them when it goes out alone
+    image: Option<RgbImage>,
+    previous_image: Option<RgbImage>,
+    at: Instant,
+}
+
+impl DigestEntry {
+    /// cert, error, unchanged & text alerts land in the digest too but aren't the page changing
+    fn is_change(&self) -> bool {
+        self.score.is_some() || self.merch
+    }
+}
+
+/// what a followed iframe shows, frames without a real src (srcdoc, about:blank) only have their document
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FrameContent {
+    Src(String),
+    Html(String),
+}
 
 #[derive(Deserialize)]
 struct SitesConfig {
+    /// the file this was loaded from
+    #[serde(skip)]
+    path: PathBuf,
     sites: Vec<WebsiteDataConfig>,
-    #[serde(default)]
+    /// merch keywords of every site that doesn't set its own
+    #[serde(default, alias = "merch-keywords")]
     merch_keywords: Vec<String>,
+    /// where per-site stats are persisted
+    #[serde(default = "SitesConfig::default_state_dir")]
+    state_dir: PathBuf,
+    /// while this file exists checks keep running but no notifications are sent
+    #[serde(default = "SitesConfig::default_silence_file")]
+    silence_file: PathBuf,
+    /// minimum ms between two navigations to the same origin, sites can override it
+    #[serde(default)]
+    min_navigation_interval: u64,
+    /// load sites even if their tls certificate is invalid or self signed, sites can override it
+    #[serde(default)]
+    ignore_cert_errors: bool,
+    /// compare luminance only so color rendering differences don't count, sites can override it
+    #[serde(default)]
+    grayscale_compare: bool,
+    /// collect the changes of a cycle and send them as one notification at the end of it
+    #[serde(default)]
+    digest_mode: bool,
+    /// collect the changes for this many seconds from the first one instead of one cycle, turns on digest mode
+    digest_window: Option<u64>,
+    /// when more than this fraction of the sites change in one cycle, send one alert for all of them instead
+    coordinated_change_fraction: Option<f64>,
+    /// visit the sites in a random order every cycle
+    #[serde(default)]
+    shuffle_order: bool,
+    /// seed for the shuffle & jitter, random if not set
+    shuffle_seed: Option<u64>,
+    /// fraction the pause between cycles randomly varies by
+    #[serde(default)]
+    cycle_jitter: f64,
+    /// max random ms to wait between two sites of a cycle
+    stagger: Option<u64>,
+    /// host:port to connect to before every cycle, while it's unreachable the cycle is skipped
+    connectivity_check: Option<String>,
+    /// seconds between connectivity checks while offline
+    #[serde(default = "SitesConfig::default_offline_interval")]
+    offline_interval: u64,
+    /// when the process' memory goes over this many mb after a cycle, drop the baselines & relaunch the browser
+    max_memory_mb: Option<u64>,
+    /// exit after this many cycles in a row where every site failed (browser broken, no network)
+    fail_fast_after: Option<u64>,
+    /// seconds between notifications saying the notifier is still running
+    heartbeat_interval: Option<u64>,
+    /// notify after a site's check errored this many times in a row
+    error_alert_after: Option<u64>,
+    /// failed checks between two error alerts of the same site, 0 alerts only once per streak
+    #[serde(default = "SitesConfig::default_error_alert_cooldown")]
+    error_alert_cooldown: u64,
+    /// hide the usual automation tells (navigator.webdriver, automation flags) from anti bot checks
+    #[serde(default)]
+    stealth: bool,
+    /// width & height of the browser window, separate from the viewport
+    window_size: Option<(u32, u32)>,
+    /// downscale the captures attached to notifications to this width
+    thumbnail_width: Option<u32>,
+    /// notification backends by name, pushover from the env vars if there are none
+    #[serde(default)]
+    notifiers: BTreeMap<String, NotifierConfig>,
+    /// window where notifications are held or dropped
+    quiet_hours: Option<QuietHoursConfig>,
+    /// how sends that failed get retried
+    #[serde(default)]
+    notify_retry: RetryConfig,
+    /// cap on notifications across every site
+    rate_limit: Option<RateLimitConfig>,
+}
+
+impl SitesConfig {
+    fn default_state_dir() -> PathBuf {
+        PathBuf::from("./state")
+    }
+
+    fn default_silence_file() -> PathBuf {
+        PathBuf::from("./SILENCE")
+    }
+
+    fn default_offline_interval() -> u64 {
+        60
+    }
+
+    fn default_error_alert_cooldown() -> u64 {
+        20
+    }
+
+    fn digest(&self) -> bool {
+        self.digest_mode || self.digest_window.is_some()
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(ref dir) = cli.working_dir {
+        env::set_current_dir(dir).with_context(|| format!("failed to change to {}", dir.display()))?;
+    }
+
+    match cli.env_file {
+        Some(ref path) => {
+            dotenv::from_path(path).with_context(|| format!("failed to load env file {}", path.display()))?;
+        }
+        None if !cli.no_dotenv => {
+            dotenv::dotenv().expect("no dotenv file found");
+        }
+        None => {}
+    }
+
+    let config_path = match cli.import_snapshot {
+        Some(ref path) => snapshot::import_config(path, cli.config.as_deref())?,
+        None => cli.config.clone().unwrap_or_else(find_config),
+    };
+
+    let mut sites_config = load_config(&config_path).await?;
+
+    if let Some(Command::Validate) = cli.command {
+        let path = sites_config.path.clone();
+        let site_count = sites_config.sites.len();
+
+        let problems = validate::validate(sites_config);
+        if problems.is_empty() {
+            println!("{} is valid, {site_count} sites", path.display());
+            return Ok(());
+        }
+
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+
+        eprintln!("{} has {} problems", path.display(), problems.len());
+        std::process::exit(1);
+    }
+
+    if let Some(ref path) = cli.import_snapshot {
+        snapshot::import_state(path, &sites_config.state_dir)?;
+        println!("Imported snapshot {}", path.display());
+        return Ok(());
+    }
+
+    if let Some(ref path) = cli.export_snapshot {
+        snapshot::export(path, &sites_config.path, &sites_config.state_dir)?;
+        println!("Exported snapshot to {}", path.display());
+        return Ok(());
+    }
+
+    println!("Loaded {} sites from {}", sites_config.sites.len(), sites_config.path.display());
+
+    let _ = MIN_NAVIGATION_INTERVAL.set(sites_config.min_navigation_interval);
+    let _ = IGNORE_CERT_ERRORS.set(sites_config.ignore_cert_errors);
+    let _ = GRAYSCALE_COMPARE.set(sites_config.grayscale_compare);
+
+    if !(0.0..1.0).contains(&sites_config.cycle_jitter) {
+        panic!("cycle jitter has to be >= 0 & < 1")
+    }
+
+    if let Some(fraction) = sites_config.coordinated_change_fraction {
+        if fraction <= 0.0 || fraction > 1.0 {
+            panic!("coordinated change fraction has to be > 0 & <= 1")
+        }
+    }
+
+    if sites_config.fail_fast_after == Some(0) {
+        panic!("fail fast after has to be > 0")
+    }
+
+    if let Some(quiet_hours) = sites_config.quiet_hours.take() {
+        let _ = QUIET_HOURS.set(quiet_hours.build());
+    }
+
+    // coordinated changes can only be told apart at the end of the cycle, so they need the notifications held too
+    if sites_config.digest() || sites_config.coordinated_change_fraction.is_some() {
+        let _ = DIGEST.set(Mutex::default());
+    }
+
+    let sites = build_sites(std::mem::take(&mut sites_config.sites), &sites_config);
+
+    if sites.is_empty() {
+        panic!("no sites added")
+    }
+
+    if cli.stats {
+        for site in &sites {
+            println!("{} -> {}", site.url(), site.stats);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(url) = cli.explain {
+        let site = sites.iter()
+            .find(|site| site.url() == url)
+            .with_context(|| format!("no site with url {url}"))?;
+
+        println!("{} (as of the last cycle)\n{}", site.url(), site.stats.cooldown);
+        return Ok(());
+    }
+
+    let notifiers = Fanout::new(std::mem::take(&mut sites_config.notifiers), sites_config.thumbnail_width, sites_config.notify_retry, sites_config.rate_limit);
+    validate_sites(&sites, &notifiers.names());
+
+    // important to test
+    println!("Got notifiers {:?}", notifiers.names());
+    let _ = NOTIFIERS.set(notifiers);
+
+    if cli.test_notifications {
+        test_notifications().await;
+        return Ok(());
+    }
+
+    run_browser(sites, &sites_config).await
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    dotenv::dotenv().expect("no dotenv file found");
+fn find_config() -> PathBuf {
+    let name = CONFIG_NAMES.into_iter()
+        .find(|name| Path::new(name).exists())
+        .unwrap_or(CONFIG_NAMES[0]);
+
+    PathBuf::from(name)
+}
+
+async fn load_config(path: &Path) -> anyhow::Result<SitesConfig> {
+    // parsed loosely first so the sites of sites.d & the defaults can be filled in whatever the format
+    let mut value = read_config_value(path).await?;
+
+    for file in site_files(path) {
+        let Some(serde_json::Value::Array(sites)) = read_config_value(&file).await?
+            .as_object_mut()
+            .and_then(|file| file.remove("sites")) else {
+            anyhow::bail!("{} has no sites", file.display());
+        };
+
+        let config_sites = value.as_object_mut()
+            .with_context(|| format!("{} isn't a table", path.display()))?
+            .entry("sites")
+            .or_insert_with(|| serde_json::Value::Array(vec![]));
+
+        if let serde_json::Value::Array(config_sites) = config_sites {
+            config_sites.extend(sites);
+        }
+    }
+
+    interpolate_env(&mut value)?;
+    apply_defaults(&mut value);
+
+    let mut config: SitesConfig = serde_json::from_value(value)?;
+    config.path = path.to_path_buf();
+    Ok(config)
+}
+
+async fn read_config_value(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let contents = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(anyhow::Error::from),
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(anyhow::Error::from),
+        Some("json") => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+        _ => anyhow::bail!("unknown config format {}, use toml, yaml or json", path.display()),
+    };
+
+    value.with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// every config file in the sites.d next to the config, by name so the site order stays the same
+fn site_files(config_path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(config_path.with_file_name(SITES_DIR)) else {
+        return vec![];
+    };
+
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| ["toml", "yaml", "yml", "json"].iter().any(|e| extension == *e)))
+        .collect::<Vec<PathBuf>>();
+
+    files.sort();
+    files
+}
+
+/// js of the sites, where ${...} is a template literal rather than an env var
+const SCRIPT_KEYS: [&str; 2] = ["scripts", "decision_script"];
+
+/// expands ${VAR} in every string of the config with the env var, so secrets can stay out of it, $${ is a literal ${
+fn interpolate_env(value: &mut serde_json::Value) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::String(string) if string.contains("${") => {
+            let mut expanded = String::with_capacity(string.len());
+            let mut rest = string.as_str();
+
+            while let Some(start) = rest.find("${") {
+                if rest[..start].ends_with('$') {
+                    expanded.push_str(&rest[..start - 1]);
+                    expanded.push_str("${");
+                    rest = &rest[start + 2..];
+                    continue;
+                }
+
+                let end = rest[start..].find('}')
+                    .with_context(|| format!("unclosed ${{ in config value {string}"))?;
+                let name = &rest[start + 2..start + end];
+
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&env::var(name).with_context(|| format!("config uses env var {name} which isn't set"))?);
+                rest = &rest[start + end + 1..];
+            }
+
+            expanded.push_str(rest);
+            *string = expanded;
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                interpolate_env(value)?;
+            }
+        }
+        serde_json::Value::Object(values) => {
+            for (_, value) in values.iter_mut().filter(|(key, _)| !SCRIPT_KEYS.contains(&key.as_str())) {
+                interpolate_env(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// fills the options of the defaults table into every site that doesn't set them itself
+fn apply_defaults(config: &mut serde_json::Value) {
+    let Some(serde_json::Value::Object(defaults)) = config.as_object_mut().and_then(|config| config.remove("defaults")) else {
+        return;
+    };
+
+    let Some(sites) = config.get_mut("sites").and_then(serde_json::Value::as_array_mut) else {
+        return;
+    };
+
+    for site in sites.iter_mut().filter_map(serde_json::Value::as_object_mut) {
+        for (key, value) in &defaults {
+            site.entry(key).or_insert_with(|| value.clone());
+        }
+    }
+}
+
+fn build_sites(configs: Vec<WebsiteDataConfig>, config: &SitesConfig) -> Vec<WebsiteData> {
+    configs.into_iter()
+        .map(|site| site.build(&config.state_dir, &config.merch_keywords))
+        .collect()
+}
+
+// what can only be checked once the notifiers are built
+fn validate_sites(sites: &[WebsiteData], notifiers: &[&str]) {
+    for site in sites {
+        if let Some(name) = site.notifiers().iter().find(|name| !notifiers.contains(&name.as_str())) {
+            panic!("{} uses notifier {name} which isn't configured", site.url())
+        }
+
+        if let Some(sound) = site.sound().as_deref().filter(|sound| notify::pushover_sound(sound).is_none()) {
+            panic!("{} uses unknown pushover sound {sound}", site.url())
+        }
+    }
+}
+
+/// latest modification of the config, sites.d included (the directory itself changes when files are added or removed)
+fn config_modified(path: &Path) -> Option<SystemTime> {
+    [path.to_path_buf(), path.with_file_name(SITES_DIR)]
+        .into_iter()
+        .chain(site_files(path))
+        .filter_map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .max()
+}
+
+/// rereads the sites from the config file, sites that are still there keep their state
+async fn reload_sites(sites: &mut Vec<WebsiteData>, config: &SitesConfig) -> anyhow::Result<()> {
+    let mut reloaded_config = load_config(&config.path).await?;
+    let configs = std::mem::take(&mut reloaded_config.sites);
+
+    // the state dir can't move while running
+    reloaded_config.state_dir = config.state_dir.clone();
+
+    // the site config is validated with panics, which shouldn't take down a running notifier
+    let reloaded = std::panic::catch_unwind(move || {
+        let reloaded = build_sites(configs, &reloaded_config);
+        validate_sites(&reloaded, &NOTIFIERS.get().expect("no notifiers").names());
+        reloaded
+    });
+
+    let Ok(mut reloaded) = reloaded else {
+        anyhow::bail!("invalid site config");
+    };
+
+    if reloaded.is_empty() {
+        anyhow::bail!("no sites added");
+    }
+
+    let mut added = 0;
+    for site in &mut reloaded {
+        match sites.iter().position(|old| old.url() == site.url()) {
+            Some(index) => site.keep_state(sites.swap_remove(index)),
+            None => added += 1,
+        }
+    }
+
+    println!("Reloaded {} sites, {added} added & {} removed", reloaded.len(), sites.len());
+    *sites = reloaded;
+    Ok(())
+}
+
+async fn run_browser(mut sites: Vec<WebsiteData>, config: &SitesConfig) -> anyhow::Result<()> {
+    let (mut browser, mut page) = launch_browser(config).await?;
+
+    // retries & receipt checks go on on their own schedule instead of waiting for the next cycle
+    task::spawn(async {
+        loop {
+            NOTIFIERS.get().expect("no notifiers").poll().await;
+            sleep(Duration::from_secs(5)).await;
+        }
+    });
+
+    let mut rng = match config.shuffle_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let started = Instant::now();
+    let checks_at_start = sites.iter().map(|site| site.stats.total_checks).sum::<u64>();
+    let mut last_heartbeat = Instant::now();
+
+    let mut modified = config_modified(&config.path);
+    let mut failed_cycles = 0;
+    loop {
+        // don't burn through the sites (and fill the logs with errors) while there's no network
+        if let Some(address) = &config.connectivity_check {
+            if !is_online(address).await {
+                println!("{address} is unreachable, looks offline, checking again in {}s", config.offline_interval);
+                sleep(Duration::from_secs(config.offline_interval)).await;
+                continue;
+            }
+        }
+
+        // only the sites are reloaded, everything else in the config needs a restart
+        let now_modified = config_modified(&config.path);
+        if now_modified != modified {
+            modified = now_modified;
+
+            if let Err(e) = reload_sites(&mut sites, config).await {
+                eprintln!("Error reloading {}, keeping the old sites -> {e:?}", config.path.display());
+            }
+        }
+
+        println!("--- CYCLE START ---");
+
+        if config.shuffle_order {
+            sites.shuffle(&mut rng);
+        }
+
+        let silenced = config.silence_file.exists();
+        SILENCED.store(silenced, atomic::Ordering::Relaxed);
+        if silenced {
+            println!("Silence file {} is present, notifications are muted", config.silence_file.display());
+        }
+
+        let mut any_succeeded = false;
+        let mut checked = 0;
+        for site in &mut sites {
+            if !site.enabled() || !site.due_in().is_zero() {
+                continue;
+            }
+
+            // spread the checks out a bit so they don't land at perfectly regular times
+            if let Some(stagger) = config.stagger.filter(|_| checked > 0) {
+                sleep(Duration::from_millis(rng.gen_range(0..=stagger))).await;
+            }
+
+            checked += 1;
+            site.check_started(&mut rng);
+
+            match check_site(&page, site).await {
+                Ok(()) => {
+                    any_succeeded = true;
+                    site.check_succeeded();
+                }
+                Err(e) => {
+                    site.stats.total_errors += 1;
+                    eprintln!("Error checking site {} -> {e:?}", site.url());
+
+                    let failed = config.error_alert_after.and_then(|after| site.check_failed(after, config.error_alert_cooldown));
+                    if let Some(failed) = failed {
+                        let message = format!("Checking {} failed {failed} times in a row, last error: {e}", site.url());
+                        notify(site, 0, &message, None, false).await;
+                    }
+                }
+            }
+
+            site.stats.cooldown = site.cooldown_state();
+            if let Err(e) = site.stats.save(site.stats_path()).await {
+                eprintln!("Error saving stats for site {} -> {e:?}", site.url());
+            }
+        }
+
+        send_digest(config, sites.iter().filter(|site| site.enabled()).count()).await;
+        send_quiet_queue().await;
+
+        if config.heartbeat_interval.is_some_and(|interval| last_heartbeat.elapsed() >= Duration::from_secs(interval)) {
+            last_heartbeat = Instant::now();
+
+            let checks = sites.iter().map(|site| site.stats.total_checks).sum::<u64>().saturating_sub(checks_at_start);
+            send_heartbeat(&sites, started.elapsed(), checks).await;
+        }
+
+        // a cycle where no site was due says nothing about whether they're failing
+        if checked > 0 {
+            failed_cycles = if any_succeeded { 0 } else { failed_cycles + 1 };
+        }
+
+        if config.fail_fast_after.is_some_and(|cycles| failed_cycles >= cycles) {
+            let summary = sites.iter()
+                .map(|site| format!("{} -> {}", site.url(), site.stats))
+                .collect::<Vec<String>>()
+                .join("\n");
+            let message = format!("Every site failed for {failed_cycles} cycles in a row, exiting\n{summary}");
+            eprintln!("{message}");
+
+            // straight to the backends, a rate limit slot or a retry later would never go out before the exit
+            let notification = Notification {
+                title: "Website Change Notifier Stopped",
+                site: None,
+                url: None,
+                url_title: None,
+                priority: 1,
+                message: &message,
+                score: None,
+                merch: false,
+                sound: None,
+                image: None,
+                previous_image: None,
+            };
+
+            if !silenced {
+                for (name, result) in NOTIFIERS.get().expect("no notifiers").send_each(&[], &notification).await {
+                    if let Err(e) = result {
+                        eprintln!("Error sending message through {name} {e:?}");
+                    }
+                }
+            }
+
+            std::process::exit(TOTAL_FAILURE_EXIT_CODE);
+        }
+
+        let _ = page.goto("about:blank").await;
+        println!("--- CYCLE END ---");
+
+        // the baselines are full size captures, so memory can creep up with many big sites
+        if let (Some(limit), Some(rss)) = (config.max_memory_mb, rss_mb()) {
+            if rss > limit {
+                println!("Using {rss}mb which is over the {limit}mb limit, dropping baselines & relaunching the browser");
+
+                for site in &mut sites {
+                    site.drop_baselines();
+                }
+
+                let _ = browser.close().await;
+                (browser, page) = launch_browser(config).await?;
+            }
+        }
+
+        // scheduled sites can be due before the usual pause is over, when every site is scheduled it's their next due time
+        let every_cycle = sites.iter().any(|site| site.enabled() && !site.is_scheduled());
+        let pause = sites.iter()
+            .filter(|site| site.enabled() && site.is_scheduled())
+            .map(WebsiteData::due_in)
+            .fold(Duration::from_secs(if every_cycle { CYCLE_PAUSE } else { IDLE_PAUSE }), Duration::min)
+            .mul_f64(1.0 + rng.gen_range(-config.cycle_jitter..=config.cycle_jitter));
+
+        // instants stop while the machine sleeps but the wall clock doesn't, a big gap means it slept & the browser may be stale
+        let before = SystemTime::now();
+        sleep(pause).await;
+
+        if before.elapsed().is_ok_and(|slept| slept > pause + Duration::from_secs(60)) {
+            println!("Woke up from sleep, relaunching the browser");
+
+            let _ = browser.close().await;
+            (browser, page) = launch_browser(config).await?;
+        }
+    }
+}
+
+// resident memory of this process, only on linux
+fn rss_mb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status.lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kb / 1024)
+}
+
+// tcp connect to the address, enough to tell if there's any network
+async fn is_online(address: &str) -> bool {
+    matches!(
+        tokio::time::timeout(Duration::from_secs(5), tokio::net::TcpStream::connect(address)).await,
+        Ok(Ok(_))
+    )
+}
+
+async fn launch_browser(config: &SitesConfig) -> anyhow::Result<(Browser, Page)> {
+    let mut browser_config = BrowserConfigBuilder::default()
+        .request_timeout(Duration::from_secs(5));
+
+    if let Some((width, height)) = config.window_size {
+        browser_config = browser_config.window_size(width, height);
+    }
+
+    if config.stealth {
+        browser_config = browser_config.disable_default_args().args(STEALTH_ARGS);
+    }
+
+    let (browser, mut handler) = Browser::launch(browser_config.build().unwrap()).await?;
+
+    #[allow(clippy::let_underscore_future)]
+        let _ = task::spawn(async move {
+        while let Some(h) = handler.next().await {
+            if let Err(e) = h {
+                eprintln!("handler error -> {e:?}");
+                break;
+            }
+        }
+    });
+
+    let page = browser.new_page("about:blank").await?;
+    if config.stealth {
+        // this sets its own (older) user agent, so it has to go before ours
+        page.enable_stealth_mode().await?;
+    }
+
+    page.set_user_agent(USER_AGENT).await?;
+
+    // an open dialog blocks the page until it's handled, so handle them as soon as they show up
+    let mut dialogs = page.event_listener::<EventJavascriptDialogOpening>().await?;
+    let dialog_page = page.clone();
+
+    #[allow(clippy::let_underscore_future)]
+        let _ = task::spawn(async move {
+        while let Some(dialog) = dialogs.next().await {
+            let accept = ACCEPT_DIALOGS.load(atomic::Ordering::Relaxed);
+            println!("{} -> {:?} dialog \"{}\", accept={accept}", dialog.url, dialog.r#type, dialog.message);
+
+            if let Err(e) = dialog_page.execute(HandleJavaScriptDialogParams::new(accept)).await {
+                eprintln!("Error handling dialog -> {e:?}");
+            }
+        }
+    });
+
+    Ok((browser, page))
+}
+
+async fn check_site(page: &Page, site: &mut WebsiteData) -> anyhow::Result<()> {
+    if !site.should_website_request() {
+        return Ok(());
+    }
+
+    site.run();
+
+    let changes = check_changes(page, site).await;
+
+    if changes.is_ok() {
+        if let Some(days) = site.cert_expiring() {
+            let message = format!("The certificate of {} expires in {days} days", site.url());
+            notify(site, 0, &message, None, false).await;
+        }
+
+        if let Some(cycles) = site.unchanged_too_long() {
+            let message = format!("{} hasn't changed in {cycles} cycles, is it still working?", site.url());
+            notify(site, -1, &message, None, false).await;
+        }
+    }
+
+    // the text alerts don't depend on the change detection, so they still run when it failed
+    let text_alerts = check_text_alerts(page, site, changes.is_err()).await;
+    changes.and(text_alerts)
+}
+
+async fn check_changes(page: &Page, site: &mut WebsiteData) -> anyhow::Result<()> {
+    if let Some(selector) = site.feed_selector().clone() {
+        return check_feed(page, site, selector).await;
+    }
+
+    if let Some(presence) = site.watch_presence().clone() {
+        return check_presence(page, site, presence).await;
+    }
+
+    if site.compare_mode() {
+        return check_compare(page, site).await;
+    }
+
+    // every page of the site gets its own baselines, but they share the latch, cooldown & notification
+    let mut first_run = false;
+    let mut outcomes = vec![];
+    for i in 0..site.urls().len() {
+        site.switch_page(i);
+        first_run |= site.last_image.is_none();
+
+        if let Some(outcome) = check_page(page, site).await? {
+            outcomes.push((i, outcome));
+        }
+    }
+
+    if outcomes.is_empty() {
+        publish_check(site, site.url(), None, site.change_latched()).await;
+        site.nothing_changed();
+        return Ok(());
+    }
+
+    let any_fired = outcomes.iter().any(|(_, outcome)| !outcome.fired.is_empty());
+    let best_score = outcomes.iter().map(|(_, outcome)| outcome.best_score).fold(1.0, f64::min);
+    let mut all_changed = site.latch_change(any_fired, best_score);
+
+    // a forced alert doesn't come from the score, so the score based checks further down don't apply to it
+    let mut forced = false;
+    if let Some(script) = site.decision_script().clone() {
+        let decided = page.evaluate(script).await?.value().is_some_and(is_truthy);
+        println!("{} -> decision={decided}", site.url());
+
+        all_changed = match site.decision() {
+            Decision::Force => all_changed || decided,
+            Decision::Gate => all_changed && decided,
+        };
+        forced = decided && matches!(site.decision(), Decision::Force);
+    }
+
+    // if get css of page then it always has shop or store or whatever
+    let mut merch_newly_detected = outcomes.iter().any(|(_, outcome)| {
+        let text = outcome.html.to_lowercase();
+        site.merch_keywords()
+            .iter()
+            .any(|k| text.contains(k))
+    });
+
+    // if merch is already detected before, its not newly detected, otherwise set the merch already detected struct value to the new one
+    if site.merch_already_detected {
+        merch_newly_detected = false;
+    } else {
+        site.merch_already_detected = merch_newly_detected;
+    }
+
+    // one result for the whole site once everything decided, a site still latched counts as changed so incidents stay open
+    let site_changed = all_changed || merch_newly_detected || site.change_latched();
+    publish_check(site, site.url(), Some(best_score), site_changed).await;
+
+    // nothing happened, run some stuff to ease off cooldown
+    if !all_changed && !merch_newly_detected {
+        site.nothing_changed();
+        return Ok(());
+    }
+
+    if first_run {
+        return Ok(());
+    }
+
+    site.stats.record_change();
+
+    let changed = outcomes.iter()
+        .filter(|(_, outcome)| !outcome.fired.is_empty())
+        .collect::<Vec<_>>();
+
+    // the changed pages' score, or every page's when only merch showed up
+    let average = changed.iter().map(|(_, outcome)| outcome.average)
+        .reduce(f64::min)
+        .or_else(|| outcomes.iter().map(|(_, outcome)| outcome.average).reduce(f64::min))
+        .unwrap_or(1.0);
+    let changed_pages = changed.iter()
+        .map(|(i, outcome)| {
+            let granularity = if site.regions().is_empty() { String::new() } else { format!(" ({})", outcome.fired.join(", ")) };
+            format!("{}{granularity}", site.urls()[*i])
+        })
+        .collect::<Vec<String>>();
+
+    let changed_pages = if changed_pages.is_empty() { site.url().to_string() } else { changed_pages.join(", ") };
+    let mut values = template::alert_values(site.url(), Some(average), merch_newly_detected);
+    values.push(("pages", changed_pages));
+    let mut message = template::render(site.message_template(), &values);
+    if forced && !any_fired {
+        message.push_str(" The decision script fired.");
+    }
+
+    if site.save_html() {
+        for (i, outcome) in &outcomes {
+            site.switch_page(*i);
+
+            match html_history::save(&site.html_dir(), &stats::state_key(site.page_url()), &outcome.html).await {
+                Ok(Some(previous)) if site.html_diff() && (changed.is_empty() || !outcome.fired.is_empty()) => {
+                    message.push('\n');
+                    message.push_str(&html_history::diff(&previous, &outcome.html, 600));
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Error saving html for site {} -> {e:?}", site.page_url()),
+            }
+        }
+    }
+
+    // so the thumbnail shows the page that changed
+    if let Some((i, _)) = changed.first() {
+        site.switch_page(*i);
+    }
+
+    // graduated priority from how much the site changed, merch always gets at least high priority
+    let priority = site.priority_for(average).unwrap_or(site.priority());
+    let priority = if merch_newly_detected { priority.max(1) } else { priority };
+
+    if !merch_newly_detected && !forced && !site.far_enough_from_last_alert(average) {
+        println!("{} -> {average} isn't far enough below the last alert, skipping", site.url());
+        return Ok(());
+    }
+
+    if !merch_newly_detected && !forced && site.already_alerted() {
+        println!("{} -> looks exactly like a recently alerted capture, skipping", site.url());
+        return Ok(());
+    }
+
+    if !site.in_warmup() && site.should_send_notification(silenced()) {
+        site.alerted_at(average);
+        notify(site, priority, &message, Some(average), merch_newly_detected).await;
+    }
+
+    Ok(())
+}
+
+/// result of comparing one of the pages of a site
+struct PageOutcome {
+    average: f64,
+    best_score: f64,
+    /// which granularities changed, the whole capture and/or any of the regions
+    fired: Vec<String>,
+    html: String,
+}
+
+// compares the current page against its baselines, none if it was skipped (text pre filter or a failed comparison)
+async fn check_page(page: &Page, site: &mut WebsiteData) -> anyhow::Result<Option<PageOutcome>> {
+    // cheap pre filter, if the text didn't change then don't bother capturing
+    let mut navigated = false;
+    if let Some(selector) = site.text_prefilter().clone() {
+        navigate(page, site, false).await?;
+        navigated = true;
+
+        let text = page.evaluate(format!(
+            "document.querySelector({})?.innerText ?? ''",
+            serde_json::to_string(&selector)?
+        )).await?.into_value::<String>()?;
+
+        if !site.text_changed(&text) && site.last_image.is_some() {
+            println!("{} -> text unchanged", site.page_url());
+            return Ok(None);
+        }
+    }
+
+    let last_image = site.last_image.take();
+    let region_images = std::mem::take(&mut site.region_images);
+
+    // check if the site changed, if it did change check up to the max confirms times (just once when confirming is off)
+    let samples = site.samples();
+    let mut screenshot_scores = vec![];
+    let mut region_scores = vec![vec![]; site.regions().len()];
+    for i in 0..samples {
+        let sample = if i == 0 && navigated {
+            capture_screenshot(page, site, &last_image, &region_images).await
+        } else if i != 0 && site.confirm_hard_reload() {
+            match hard_reload(page, site).await {
+                Ok(()) => capture_screenshot(page, site, &last_image, &region_images).await,
+                Err(e) => Err(e),
+            }
+        } else {
+            create_screenshot(page, site, i != 0, &last_image, &region_images).await
+        };
+
+        // a capture that can't be decoded or compared gets one retry from a fresh navigation
+        let sample = match sample {
+            Err(e) if e.is::<CompareError>() => {
+                println!("{} -> {e}, retrying the capture", site.page_url());
+                create_screenshot(page, site, true, &last_image, &region_images).await
+            }
+            sample => sample,
+        };
+
+        let (result, region_results) = match sample {
+            Ok(sample) => sample,
+            // keep the old baselines, and never alert off of a comparison that failed
+            Err(e) => {
+                site.last_image = last_image;
+                site.region_images = region_images;
+
+                if e.is::<CompareError>() {
+                    eprintln!("{} -> {e}, skipping this cycle", site.page_url());
+                    return Ok(None);
+                }
+
+                return Err(e);
+            }
+        };
+
+        let unchanged = sample_unchanged(site, &result, &region_results);
+
+        screenshot_scores.push(result);
+        for (scores, region_result) in region_scores.iter_mut().zip(region_results) {
+            scores.push(region_result);
+        }
+
+        if unchanged || i + 1 == samples {
+            break;
+        }
+
+        sleep(Duration::from_millis(250)).await;
+    }
+
+    let only_scores = screenshot_scores.iter().map(|(s, _)| *s).collect::<Vec<f64>>();
+    let average = only_scores.iter().sum::<f64>() / only_scores.len() as f64;
+
+    println!("{} -> avg={average},all={:?}", site.page_url(), only_scores);
+    site.stats.record_score(average);
+    if last_image.is_some() {
+        site.record_warmup_score(average);
+    }
+
+    // with smoothing the moving average decides instead of the raw scores, so one off dips don't alert
+    let main_changed = match site.update_score_ema(average) {
+        Some(ema) => {
+            println!("{} -> ema={ema}", site.page_url());
+            ema < site.threshold()
+        }
+        None => only_scores.iter().all(|s| *s < site.threshold()),
+    };
+
+    let mut fired = vec![];
+    if main_changed {
+        fired.push(site.screenshot_selector().as_deref().unwrap_or("full page").to_string());
+    }
+
+    for (scores, region) in region_scores.iter().zip(site.regions()) {
+        let region_scores_only = scores.iter().map(|(s, _)| *s).collect::<Vec<f64>>();
+        println!("{} [{}] -> all={:?}", site.page_url(), region.selector(), region_scores_only);
+
+        if region_scores_only.iter().all(|s| *s < region.threshold()) {
+            fired.push(region.selector().to_string());
+        }
+    }
+
+    // one slow last look, in case the fast samples all caught the page mid load
+    if let Some(delay) = site.final_verify_delay().filter(|_| !fired.is_empty()) {
+        sleep(Duration::from_millis(delay)).await;
+
+        match create_screenshot(page, site, true, &last_image, &region_images).await {
+            Ok((result, region_results)) => {
+                if sample_unchanged(site, &result, &region_results) {
+                    println!("{} -> settled back after {delay}ms, not a change", site.page_url());
+                    fired.clear();
+                }
+
+                screenshot_scores.push(result);
+                for (scores, region_result) in region_scores.iter_mut().zip(region_results) {
+                    scores.push(region_result);
+                }
+            }
+            Err(e) => eprintln!("{} -> final verification failed, going with the fast samples -> {e:?}", site.page_url()),
+        }
+    }
+
+    let best_score = screenshot_scores.iter().map(|(s, _)| *s).fold(0.0, f64::max);
+
+    if let (Some(last_image), Some((_, image))) = (&last_image, screenshot_scores.first()) {
+        site.learn_mask(last_image, image);
+    }
+
+    site.last_image = Some(most_similar(site, screenshot_scores).context("no screenshots?")?.1);
+    // only held on to while there's a change to send it with, captures are big
+    site.previous_image = last_image.filter(|_| !fired.is_empty());
+    site.region_images = region_scores.into_iter()
+        .map(|scores| most_similar(site, scores).map(|(_, image)| image))
+        .collect();
+
+    let html = page.evaluate("document.body.outerHTML").await?.into_value::<String>()?;
+
+    Ok(Some(PageOutcome { average, best_score, fired, html }))
+}
+
+async fn navigate(page: &Page, site: &mut WebsiteData, confirmation: bool) -> anyhow::Result<()> {
+    // the page is shared between sites, so always set it so it doesn't leak over from another site
+    page.execute(SetCacheDisabledParams::new(site.disable_cache())).await?;
+    page.set_user_agent(site.user_agent().as_deref().unwrap_or(USER_AGENT)).await?;
+    page.execute(SetExtraHttpHeadersParams::new(Headers::new(serde_json::json!(site.headers())))).await?;
+
+    match site.device_metrics() {
+        Some(metrics) => {
+            page.execute(SetDeviceMetricsOverrideParams::new(metrics.width as i64, metrics.height as i64, metrics.scale, metrics.mobile)).await?;
+        }
+        None => {
+            page.execute(ClearDeviceMetricsOverrideParams::default()).await?;
+        }
+    }
+    page.execute(SetTouchEmulationEnabledParams::new(site.device_metrics().is_some_and(|metrics| metrics.mobile))).await?;
+
+    ACCEPT_DIALOGS.store(matches!(site.dialog_action(), DialogAction::Accept), atomic::Ordering::Relaxed);
+    page.execute(SetIgnoreCertificateErrorsParams::new(
+        site.ignore_cert_errors().unwrap_or(*IGNORE_CERT_ERRORS.get_or_init(|| false))
+    )).await?;
+
+    if !site.cookies().is_empty() {
+        let cookies = site.cookies().iter()
+            .map(|cookie| {
+                let mut param = CookieParam::new(cookie.name(), cookie.value());
+                param.path = cookie.path().clone();
+
+                // a cookie needs a domain or a url to belong to
+                match cookie.domain() {
+                    Some(domain) => param.domain = Some(domain.clone()),
+                    None => param.url = Some(site.page_url().to_string()),
+                }
+
+                param
+            })
+            .collect();
+
+        page.execute(SetCookiesParams::new(cookies)).await?;
+    }
+
+    wait_for_origin(site, site.page_url()).await;
+
+    // listen before navigating, the certificate comes with the document's response
+    let responses = match site.cert_expiry_days() {
+        Some(_) => Some(page.event_listener::<EventResponseReceived>().await?),
+        None => None,
+    };
+
+    goto(page, site, site.page_url()).await?;
+
+    if let Some(mut responses) = responses {
+        let valid_to = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(event) = responses.next().await {
+                if event.r#type == ResourceType::Document {
+                    return event.response.security_details.as_ref().map(|details| *details.valid_to.inner() as u64);
+                }
+            }
+
+            None
+        }).await;
+
+        site.cert_valid_to = valid_to.ok().flatten();
+    }
+
+    // follow the frame so capturing & evaluating happens on the frame's own content
+    if let Some(selector) = site.iframe_selector() {
+        let content = page.evaluate(format!(
+            "(() => {{
+                const frame = document.querySelector({});
+                if (!frame) return null;
+                if (frame.hasAttribute('srcdoc') || !frame.src || frame.src === 'about:blank') {{
+                    const html = frame.contentDocument?.documentElement?.outerHTML;
+                    return html ? {{ html }} : null;
+                }}
+                return {{ src: frame.src }};
+            }})()",
+            serde_json::to_string(selector)?
+        )).await?.into_value::<Option<FrameContent>>()?.context("iframe not found or its document can't be read")?;
+
+        match content {
+            FrameContent::Src(src) => {
+                wait_for_origin(site, &src).await;
+                goto(page, site, &src).await?;
+            }
+            // the page keeps the outer url, so relative urls in the frame still resolve like they did inside of it
+            FrameContent::Html(html) => {
+                page.set_content(html).await?;
+            }
+        }
+    }
 
-    let sites_config: SitesConfig = toml::from_str(
-        &tokio::fs::read_to_string("./sites.toml").await?
-    )?;
+    prepare_page(page, site, confirmation).await;
+    Ok(())
+}
 
-    println!("Loaded {} sites from toml file", sites_config.sites.len());
+async fn goto(page: &Page, site: &WebsiteData, url: &str) -> anyhow::Result<()> {
+    let Some(capture_after) = site.capture_after() else {
+        page.goto(url).await?;
+        page.wait_for_navigation().await?;
+        return Ok(());
+    };
 
-    let _ = MERCH_KEYWORDS.set(sites_config.merch_keywords);
+    // listen before navigating so events that fire before goto returns don't get missed
+    page.execute(SetLifecycleEventsEnabledParams::new(true)).await?;
+    let mut events = page.event_listener::<EventLifecycleEvent>().await?;
 
-    let sites = sites_config.sites
-        .into_iter()
-        .map(WebsiteDataConfig::build)
-        .collect::<Vec<WebsiteData>>();
+    page.goto(url).await?;
+    page.wait_for_navigation().await?;
 
-    if sites.is_empty() {
-        panic!("no sites added")
+    let main_frame = page.mainframe().await?;
+    let waited = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.next().await {
+            if event.name == capture_after.event_name() && main_frame.as_ref().is_none_or(|f| *f == event.frame_id) {
+                break;
+            }
+        }
+    }).await;
+
+    if waited.is_err() {
+        println!("{url} -> timed out waiting for {}", capture_after.event_name());
     }
 
-    let keys = (
-        env::var("PUSHOVER_USER_KEY").expect("no pushover user key env var"),
-        env::var("PUSHOVER_APP_TOKEN").expect("no pushover app token env var")
-    );
+    Ok(())
+}
 
-    let _ = PUSHOVER_KEYS.set(keys);
+// reloads whatever is loaded bypassing the cache (and service workers), for trustworthy confirmations
+async fn hard_reload(page: &Page, site: &WebsiteData) -> anyhow::Result<()> {
+    wait_for_origin(site, site.page_url()).await;
 
-    // important to test
-    println!("Got pushover keys {:?}", PUSHOVER_KEYS.get().expect("no pushover keys"));
+    page.execute(ReloadParams::builder().ignore_cache(true).build()).await?;
+    page.wait_for_navigation().await?;
 
-    run_browser(sites).await
+    prepare_page(page, site, true).await;
+    Ok(())
 }
 
-async fn run_browser(mut sites: Vec<WebsiteData>) -> anyhow::Result<()> {
-    let (browser, mut handler) = Browser::launch(
-        BrowserConfigBuilder::default()
-            .request_timeout(Duration::from_secs(5))
-            .build()
-            .unwrap()
-    ).await?;
+async fn prepare_page(page: &Page, site: &WebsiteData, confirmation: bool) {
+    // run all scripts that apply
+    for script in site.scripts() {
+        let should_run = match script.when() {
+            ScriptCondition::Always => true,
+            ScriptCondition::FirstRun => site.get_runs() <= 1,
+            ScriptCondition::Confirmation => confirmation,
+            ScriptCondition::IfSelector(selector) => match serde_json::to_string(selector) {
+                Ok(selector) => page.evaluate(format!("document.querySelector({selector}) !== null"))
+                    .await
+                    .ok()
+                    .and_then(|r| r.into_value::<bool>().ok())
+                    .unwrap_or_default(),
+                Err(_) => false,
+            },
+        };
 
-    #[allow(clippy::let_underscore_future)]
-        let _ = task::spawn(async move {
-        while let Some(h) = handler.next().await {
-            if let Err(e) = h {
-                eprintln!("handler error -> {e:?}");
-                break;
-            }
+        if should_run {
+            let _ = page.evaluate(script.code()).await;
         }
-    });
+    }
 
-    let page = browser.new_page("about:blank").await?;
-    page.set_user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36").await?;
+    if site.wait() != 0 {
+        sleep(Duration::from_millis(site.wait())).await;
+    }
+}
 
-    loop {
-        println!("--- CYCLE START ---");
+// spaces out navigations to the same origin, across sites and confirmation samples
+async fn wait_for_origin(site: &WebsiteData, url: &str) {
+    let interval = Duration::from_millis(
+        site.min_navigation_interval().unwrap_or(*MIN_NAVIGATION_INTERVAL.get_or_init(|| 0))
+    );
 
-        for site in &mut sites {
-            if let Err(e) = check_site(&page, site).await {
-                eprintln!("Error checking site {} -> {e:?}", site.url());
-            }
-        }
+    let origin = Url::parse(url)
+        .map(|u| u.origin().ascii_serialization())
+        .unwrap_or_else(|_| url.to_string());
 
-        let _ = page.goto("about:blank").await;
-        println!("--- CYCLE END ---");
+    let wait = {
+        let mut last_navigations = LAST_NAVIGATIONS.lock().unwrap();
+        let now = Instant::now();
+        let next = last_navigations.get(&origin)
+            .map(|last| *last + interval)
+            .filter(|next| *next > now)
+            .unwrap_or(now);
 
-        sleep(Duration::from_secs(25)).await;
+        last_navigations.insert(origin, next);
+        next - now
+    };
+
+    if !wait.is_zero() {
+        sleep(wait).await;
     }
 }
 
-async fn check_site(page: &Page, site: &mut WebsiteData) -> anyhow::Result<()> {
-    if !site.should_website_request() {
+// alerts on phrases showing up or disappearing, independent of the change detection & its cooldown
+async fn check_text_alerts(page: &Page, site: &mut WebsiteData, reload: bool) -> anyhow::Result<()> {
+    if !site.has_text_alerts() {
         return Ok(());
     }
 
-    site.run();
-
-    let first_run = site.last_image.is_none();
-    let last_image = site.last_image.take();
+    // the phrases are watched on the site's own url, compare mode & multi url sites end the check on another one.
+    // a failed check can leave the page anywhere too, an error page would look like the phrases went away
+    if reload || site.page_url() != site.url() {
+        site.switch_page(0);
+        navigate(page, site, false).await?;
+    }
 
-    // check if the site changed, if it did change check up to the max confirms times
-    let mut screenshot_scores = vec![];
-    for _ in 0..site.max_confirms() {
-        let result = create_screenshot(page, site, &last_image).await?;
-        if result.0 > site.threshold() {
-            screenshot_scores.push(result);
-            break;
-        }
+    let text = page.evaluate("document.body.innerText").await?.into_value::<String>()?;
+    let fired = site.text_alerts(&text);
+    if fired.is_empty() {
+        site.record_text(&text, false);
+        return Ok(());
+    }
 
-        screenshot_scores.push(result);
-        sleep(Duration::from_millis(250)).await;
+    if site.text_alert_on_cooldown() {
+        println!("Text alert for {} is on cooldown", site.url());
+        return Ok(());
     }
 
-    let only_scores = screenshot_scores.iter().map(|(s, _)| *s).collect::<Vec<f64>>();
-    let average = only_scores.iter().sum::<f64>() / only_scores.len() as f64;
+    let message = format!("{} {}", site.url(), fired.join(", "));
+    notify(site, site.priority(), &message, None, false).await;
+    site.record_text(&text, true);
 
-    println!("{} -> avg={average},all={:?}", site.url(), only_scores);
+    Ok(())
+}
 
-    let all_changed = only_scores.into_iter().all(|s| s < site.threshold());
-    let most_similar = screenshot_scores.into_iter()
-        .max_by(|a, b| match a.0.partial_cmp(&b.0) {
-            Some(c) => c,
-            None => {
-                eprintln!("error comparing on site {} with a={},b={}", a.0, b.0, site.url());
-                Ordering::Equal
-            }
-        }).context("no screenshots?")?;
+// feed mode, alert on items that weren't seen before instead of the visual diff
+async fn check_feed(page: &Page, site: &mut WebsiteData, selector: String) -> anyhow::Result<()> {
+    navigate(page, site, false).await?;
 
-    site.last_image = Some(most_similar.1);
+    let items = page.evaluate(format!(
+        "Array.from(document.querySelectorAll({})).map(e => e.innerText.trim())",
+        serde_json::to_string(&selector)?
+    )).await?.into_value::<Vec<String>>()?;
 
-    // if get css of page then it always has shop or store or whatever
-    let text = page.evaluate("document.body.outerHTML").await?.into_value::<String>()?.to_lowercase();
+    let Some(new_items) = site.new_feed_items(items) else {
+        println!("{} -> seeded feed", site.url());
+        return Ok(());
+    };
 
-    let mut merch_newly_detected = MERCH_KEYWORDS.get_or_init(Vec::new)
-        .iter()
-        .any(|k| text.contains(k));
+    println!("{} -> new items={}", site.url(), new_items.len());
+    publish_check(site, site.page_url(), None, !new_items.is_empty()).await;
 
-    // if merch is already detected before, its not newly detected, otherwise set the merch already detected struct value to the new one
-    if site.merch_already_detected {
-        merch_newly_detected = false;
-    } else {
-        site.merch_already_detected = merch_newly_detected;
+    if new_items.is_empty() {
+        site.nothing_changed();
+        return Ok(());
     }
 
-    // nothing happened, run some stuff to ease off cooldown
-    if !all_changed && !merch_newly_detected {
+    site.stats.record_change();
+
+    let message = format!("Found {} new items on {}:\n- {}", new_items.len(), site.url(), new_items.join("\n- "));
+
+    if site.should_send_notification(silenced()) {
+        notify(site, site.priority(), &message, None, false).await;
+    }
+
+    Ok(())
+}
+
+// presence mode, alert when the element flips into the expected state
+async fn check_presence(page: &Page, site: &mut WebsiteData, presence: PresenceConfig) -> anyhow::Result<()> {
+    navigate(page, site, false).await?;
+
+    let present = page.evaluate(format!(
+        "document.querySelector({}) !== null",
+        serde_json::to_string(presence.selector())?
+    )).await?.into_value::<bool>()?;
+
+    println!("{} -> {} present={present}", site.url(), presence.selector());
+
+    let previous = site.last_presence.replace(present);
+    let flipped = previous.is_some() && previous != Some(present) && present == presence.expects_present();
+    publish_check(site, site.page_url(), None, flipped).await;
+
+    if !flipped {
         site.nothing_changed();
         return Ok(());
     }
 
-    if first_run {
+    site.stats.record_change();
+
+    let message = format!("{} is now {} on {}", presence.selector(), if present { "present" } else { "absent" }, site.url());
+
+    if site.should_send_notification(silenced()) {
+        notify(site, site.priority(), &message, None, false).await;
+    }
+
+    Ok(())
+}
+
+// compare mode, alert when the two urls diverge from each other instead of from their own past
+async fn check_compare(page: &Page, site: &mut WebsiteData) -> anyhow::Result<()> {
+    site.switch_page(0);
+    let ((_, image), _) = create_screenshot(page, site, false, &None, &[]).await?;
+
+    site.switch_page(1);
+    let ((score, _), _) = create_screenshot(page, site, false, &Some(image), &[]).await?;
+
+    let (url_a, url_b) = (site.urls()[0].clone(), site.urls()[1].clone());
+    println!("{url_a} vs {url_b} -> {score}");
+
+    // with a clear threshold it only alerts again after they matched in between, like a normal change
+    let diverged = site.latch_change(score < site.threshold(), score);
+    publish_check(site, site.page_url(), Some(score), diverged || site.change_latched()).await;
+
+    if !diverged {
+        site.nothing_changed();
         return Ok(());
     }
 
-    let message = format!("Found changes on {} with an average difference rating of {average}.{}", site.url(), if merch_newly_detected { "MERCH DETECTED!" } else { "" });
+    site.stats.record_change();
 
-    if site.get_runs() > 3 && site.should_send_notification() {
-        notify(site, if merch_newly_detected { 1 } else { 0 }, &message).await;
+    let message = format!("{url_a} and {url_b} diverged with a difference rating of {score}.");
+    let priority = site.priority_for(score).unwrap_or(site.priority());
+
+    if site.should_send_notification(silenced()) {
+        notify(site, priority, &message, Some(score), false).await;
     }
 
     Ok(())
 }
 
-async fn create_screenshot(page: &Page, site: &mut WebsiteData, last_image: &Option<RgbImage>) -> anyhow::Result<(f64, RgbImage)> {
-    page.goto(site.url()).await?;
-    page.wait_for_navigation().await?;
+/// the result of a check for the backends that follow every check, like mqtt & the incident trackers
+async fn publish_check(site: &WebsiteData, page: &str, score: Option<f64>, changed: bool) {
+    let result = CheckResult { site: site.url(), page, score, changed };
+    NOTIFIERS.get().expect("no notifiers").check_result(site.notifiers(), &result).await;
+}
 
-    // run all scripts
-    for script in site.scripts() {
-        let _ = page.evaluate(script.as_str()).await;
+// scroll a screen at a time until the selector shows up (for infinite scroll pages), then go back to the top
+async fn scroll_until(page: &Page, selector: &str, max_scrolls: u32) -> anyhow::Result<()> {
+    let present_script = format!("document.querySelector({}) !== null", serde_json::to_string(selector)?);
+
+    let mut found = false;
+    for _ in 0..max_scrolls {
+        found = page.evaluate(present_script.as_str()).await?.into_value::<bool>()?;
+        if found {
+            break;
+        }
+
+        page.evaluate("window.scrollBy(0, window.innerHeight)").await?;
+        sleep(Duration::from_millis(250)).await;
     }
 
-    if site.wait() != 0 {
-        sleep(Duration::from_millis(site.wait())).await;
+    if !found {
+        println!("{selector} didn't show up after {max_scrolls} scrolls, capturing anyway");
+    }
+
+    page.evaluate("window.scrollTo(0, 0)").await?;
+    Ok(())
+}
+
+// chromiumoxide's full page capture forces a dpr of 1 & clears the override after, so this keeps the emulated device
+async fn full_page_emulated(page: &Page, device: DeviceMetrics) -> anyhow::Result<Vec<u8>> {
+    let metrics = page.layout_metrics().await?;
+    let (width, height) = (metrics.css_content_size.width, metrics.css_content_size.height);
+
+    page.execute(SetDeviceMetricsOverrideParams::new(width as i64, height as i64, device.scale, device.mobile)).await?;
+    page.execute(SetDefaultBackgroundColorOverrideParams { color: Some(Rgba { r: 0, g: 0, b: 0, a: Some(0.0) }) }).await?;
+
+    let capture = page.execute(CaptureScreenshotParams::builder()
+        .format(CaptureScreenshotFormat::Png)
+        .clip(Viewport { x: 0.0, y: 0.0, width, height, scale: 1.0 })
+        .build()
+    ).await;
+
+    page.execute(SetDefaultBackgroundColorOverrideParams { color: None }).await?;
+    page.execute(SetDeviceMetricsOverrideParams::new(device.width as i64, device.height as i64, device.scale, device.mobile)).await?;
+
+    let capture = capture?;
+    let data: &str = capture.result.data.as_ref();
+    Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+}
+
+// the canvas' own pixels as a png, these don't depend on layout or what's drawn over the canvas
+async fn canvas_pixels(page: &Page, selector: &str) -> anyhow::Result<Vec<u8>> {
+    let data_url = page.evaluate(format!(
+        "document.querySelector({})?.toDataURL('image/png') ?? null",
+        serde_json::to_string(selector)?
+    )).await?.into_value::<Option<String>>()?.context("canvas not found")?;
+
+    let data = data_url.strip_prefix("data:image/png;base64,").context("canvas didn't return a png")?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+}
+
+// like js, so scripts can just return whatever they compute
+fn is_truthy(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0 && !n.is_nan()),
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => true,
+    }
+}
+
+// a sample is unchanged when the capture and every region are above their thresholds
+fn sample_unchanged(site: &WebsiteData, result: &(f64, RgbImage), region_results: &[(f64, RgbImage)]) -> bool {
+    result.0 > site.threshold() && region_results.iter()
+        .zip(site.regions())
+        .all(|((s, _), region)| *s > region.threshold())
+}
+
+fn most_similar(site: &WebsiteData, screenshot_scores: Vec<(f64, RgbImage)>) -> Option<(f64, RgbImage)> {
+    screenshot_scores.into_iter()
+        .max_by(|a, b| match a.0.partial_cmp(&b.0) {
+            Some(c) => c,
+            None => {
+                eprintln!("error comparing on site {} with a={},b={}", a.0, b.0, site.url());
+                Ordering::Equal
+            }
+        })
+}
+
+async fn create_screenshot(
+    page: &Page,
+    site: &mut WebsiteData,
+    confirmation: bool,
+    last_image: &Option<RgbImage>,
+    region_images: &[Option<RgbImage>],
+) -> anyhow::Result<((f64, RgbImage), Vec<(f64, RgbImage)>)> {
+    navigate(page, site, confirmation).await?;
+    capture_screenshot(page, site, last_image, region_images).await
+}
+
+async fn capture_screenshot(
+    page: &Page,
+    site: &mut WebsiteData,
+    last_image: &Option<RgbImage>,
+    region_images: &[Option<RgbImage>],
+) -> anyhow::Result<((f64, RgbImage), Vec<(f64, RgbImage)>)> {
+    if let Some(selector) = site.scroll_until_selector() {
+        scroll_until(page, selector, site.max_scrolls()).await?;
     }
 
-    let new_screenshot_bytes = if let Some(selector) = site.screenshot_selector() {
+    let mut degraded = false;
+    let new_screenshot_bytes = if let Some(selector) = site.canvas_selector() {
+        canvas_pixels(page, selector).await?
+    } else if let Some(selector) = site.screenshot_selector() {
         page.find_element(selector)
             .await?
             .screenshot(CaptureScreenshotFormat::Png)
             .await?
     } else {
-        page.screenshot(ScreenshotParams::builder()
-            .omit_background(true)
-            .full_page(true)
-            .build()
-        ).await?
+        let full_page = match site.device_metrics() {
+            Some(device) => full_page_emulated(page, device).await,
+            None => page.screenshot(ScreenshotParams::builder()
+                .omit_background(true)
+                .full_page(true)
+                .build()
+            ).await.map_err(anyhow::Error::from),
+        };
+
+        match full_page {
+            Ok(bytes) => bytes,
+            // some pages (fixed positioning, huge canvases) can't be captured full page, so fall back to the viewport
+            Err(e) => {
+                println!("{} -> full page capture failed, degrading to viewport -> {e:?}", site.url());
+                degraded = true;
+
+                page.screenshot(ScreenshotParams::builder()
+                    .omit_background(true)
+                    .build()
+                ).await?
+            }
+        }
     };
 
     // let a: &[u8] = new_screenshot_bytes.as_ref();
     // tokio::fs::write(format!("test_{}.png", site.url().get(13..16).unwrap()), a).await?;
 
-    // compare with a few special stuff
-    let t = task::block_in_place(move || -> anyhow::Result<(f64, RgbImage)> {
-        let screenshot_image = image::load_from_memory(&new_screenshot_bytes)?.into_rgb8();
+    // don't compare full page & viewport captures against each other, start a new baseline instead
+    let last_image = if degraded != site.capture_degraded {
+        println!("{} -> capture mode changed (degraded={degraded}), starting a new baseline", site.url());
+        site.capture_degraded = degraded;
+        &None
+    } else {
+        last_image
+    };
+
+    let options = CompareOptions {
+        grayscale: site.grayscale_compare().unwrap_or(*GRAYSCALE_COMPARE.get_or_init(|| false)),
+        ..site.compare_options()
+    };
 
-        let comparison = match last_image {
-            // if the function fails, then that means the image sizes were different, which means the site was 100% updated
-            Some(ref last_image) => image_compare::rgb_hybrid_compare(&screenshot_image, last_image)
-                .map(|r| r.score)
-                .unwrap_or_default(),
-            None => 1.0,
-        };
+    let result = if site.reference_images().is_empty() {
+        compare::load_and_compare(&new_screenshot_bytes, last_image, options)?
+    } else {
+        compare::load_and_compare_best(&new_screenshot_bytes, site.reference_images(), options)?
+    };
+
+    // regions are captured from the same navigation, each compared against its own baseline
+    let mut region_results = vec![];
+    for (i, region) in site.regions().iter().enumerate() {
+        let region_bytes = page.find_element(region.selector())
+            .await?
+            .screenshot(CaptureScreenshotFormat::Png)
+            .await?;
+
+        let region_image = region_images.get(i).unwrap_or(&None);
+        region_results.push(compare::load_and_compare(&region_bytes, region_image, CompareOptions { mask: None, ..options })?);
+    }
 
-        Ok((comparison, screenshot_image))
-    })?;
+    Ok((result, region_results))
+}
 
-    Ok(t)
+fn silenced() -> bool {
+    SILENCED.load(atomic::Ordering::Relaxed)
 }
 
 async fn notify(
-    website: &WebsiteData,
+    website: &mut WebsiteData,
     priority: i8,
     message: &str,
+    score: Option<f64>,
+    merch: bool,
 ) {
+    if silenced() {
+        return;
+    }
+
+    website.stats.total_notifications += 1;
+
+    let title = template::render(website.title_template(), &template::alert_values(website.url(), score, merch));
+    let entry = || DigestEntry {
+        title: title.clone(),
+        site: website.url().to_string(),
+        notifiers: website.notifiers().clone(),
+        url: website.notify_url().to_string(),
+        url_title: website.notify_url_title().clone(),
+        priority,
+        message: message.to_string(),
+        score,
+        merch,
+        sound: website.sound().clone(),
+        image: website.last_image.clone(),
+        previous_image: website.previous_image.clone(),
+        at: Instant::now(),
+    };
+
+    if let Some(quiet_hours) = QUIET_HOURS.get() {
+        if quiet_hours.is_quiet(chrono::Utc::now()) && !(merch && quiet_hours.merch_bypass()) {
+            if quiet_hours.queue() {
+                println!("Quiet hours, holding the notification for {}...", website.url());
+                QUIET_QUEUE.lock().unwrap().push(entry());
+            } else {
+                println!("Quiet hours, dropping the notification for {}", website.url());
+            }
+
+            return;
+        }
+    }
+
+    if let Some(digest) = DIGEST.get() {
+        println!("Adding {} to the digest...", website.url());
+        digest.lock().unwrap().push(entry());
+        return;
+    }
+
     println!("Notifying for {}...", website.url());
 
-    let (user_key, app_token) = PUSHOVER_KEYS.get().expect("no pushover keys");
+    let notification = Notification {
+        title: &title,
+        site: Some(website.url()),
+        url: Some(website.notify_url()),
+        url_title: website.notify_url_title().as_deref(),
+        priority,
+        message,
+        score,
+        merch,
+        sound: website.sound().as_deref(),
+        image: website.last_image.as_ref(),
+        previous_image: website.previous_image.as_ref(),
+    };
+
+    NOTIFIERS.get().expect("no notifiers").send(website.notifiers(), &notification).await;
+}
+
+// sends everything collected this cycle, as one notification in digest mode or one per change otherwise
+async fn send_digest(config: &SitesConfig, site_count: usize) {
+    let Some(digest) = DIGEST.get() else {
+        return;
+    };
+
+    // the window starts with the first change, so a change waits at most that long
+    if let Some(window) = config.digest_window {
+        let first = digest.lock().unwrap().first().map(|entry| entry.at);
+        if first.is_some_and(|at| at.elapsed() < Duration::from_secs(window)) {
+            return;
+        }
+    }
+
+    let entries = std::mem::take(&mut *digest.lock().unwrap());
+
+    // lots of sites changing at once is usually one upstream cause (cdn, shared template, own network)
+    // with a digest window a site can have entries from several cycles
+    let mut changed_sites = entries.iter()
+        .filter(|entry| entry.is_change())
+        .map(|entry| entry.site.as_str())
+        .collect::<Vec<&str>>();
+    changed_sites.sort();
+    changed_sites.dedup();
+
+    if let Some(fraction) = config.coordinated_change_fraction {
+        if changed_sites.len() > 1 && changed_sites.len() as f64 / site_count as f64 > fraction {
+            println!("Notifying coordinated change of {}/{site_count} sites...", changed_sites.len());
+
+            let priority = entries.iter().filter(|entry| entry.is_change()).map(|entry| entry.priority).max().unwrap_or_default();
+            let during = match config.digest_window {
+                Some(window) => format!("in the last {window}s"),
+                None => String::from("this cycle"),
+            };
+            let message = format!("{}/{site_count} sites changed {during}:\n{}", changed_sites.len(), changed_sites.join("\n"));
 
-    let message = MessageBuilder::new(user_key, app_token, message)
-        .set_title("Website Change Detected")
-        .set_url(website.url(), None)
-        .set_priority(priority)
-        .build();
+            send_notification(&combined_notifiers(&entries), "Coordinated Change Detected", None, None, priority, &message).await;
 
-    if let Err(e) = send_pushover_request(message).await {
-        eprint!("Error sending message {e:?}");
+            // the rest isn't part of the change so it still goes out on its own
+            for entry in entries.iter().filter(|entry| !entry.is_change()) {
+                send_entry(entry).await;
+            }
+
+            return;
+        }
+    }
+
+    if !config.digest() {
+        for entry in &entries {
+            send_entry(entry).await;
+        }
+
+        return;
+    }
+
+    send_combined(&entries).await;
+}
+
+// so a monitor that silently died can be told apart from one where nothing changed
+async fn send_heartbeat(sites: &[WebsiteData], uptime: Duration, checks: u64) {
+    let uptime = uptime.as_secs();
+    let mut message = format!("Up for {}h{}m, {checks} checks since the start", uptime / 3600, uptime % 3600 / 60);
+
+    let on_cooldown = sites.iter()
+        .filter(|site| site.cooldown_left() > 0)
+        .map(|site| format!("{} ({} cycles)", site.url(), site.cooldown_left()))
+        .collect::<Vec<String>>();
+
+    if on_cooldown.is_empty() {
+        message.push_str("\nNo sites on cooldown");
+    } else {
+        message.push_str(&format!("\nOn cooldown:\n{}", on_cooldown.join("\n")));
+    }
+
+    let disabled = sites.iter().filter(|site| !site.enabled()).count();
+    if disabled > 0 {
+        message.push_str(&format!("\n{disabled} sites disabled"));
+    }
+
+    println!("Sending heartbeat...");
+    send_notification(&[], "Website Change Notifier Heartbeat", None, None, -1, &message).await;
+}
+
+// sends what was held back during the quiet hours once they're over
+async fn send_quiet_queue() {
+    let Some(quiet_hours) = QUIET_HOURS.get() else {
+        return;
+    };
+
+    if quiet_hours.is_quiet(chrono::Utc::now()) {
+        return;
+    }
+
+    let entries = std::mem::take(&mut *QUIET_QUEUE.lock().unwrap());
+    if !entries.is_empty() {
+        println!("Quiet hours are over, sending {} held notifications...", entries.len());
+        send_combined(&entries).await;
+    }
+}
+
+/// one notification for all of the entries, or the entry itself if there's just one
+async fn send_combined(entries: &[DigestEntry]) {
+    match entries {
+        [] => {}
+        [entry] => send_entry(entry).await,
+        _ => {
+            println!("Notifying digest of {} sites...", entries.len());
+
+            let priority = entries.iter().map(|entry| entry.priority).max().unwrap_or_default();
+            let message = entries.iter()
+                .map(|entry| entry.message.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n");
+
+            send_notification(&combined_notifiers(entries), &format!("{} Website Changes Detected", entries.len()), None, None, priority, &message).await;
+        }
+    }
+}
+
+/// combined notifications go through every backend any of the sites uses
+fn combined_notifiers(entries: &[DigestEntry]) -> Vec<String> {
+    if entries.iter().any(|entry| entry.notifiers.is_empty()) {
+        return vec![];
+    }
+
+    let mut notifiers = entries.iter().flat_map(|entry| entry.notifiers.clone()).collect::<Vec<String>>();
+    notifiers.sort();
+    notifiers.dedup();
+    notifiers
+}
+
+async fn send_entry(entry: &DigestEntry) {
+    // silenced since the entry was held back
+    if silenced() {
+        return;
+    }
+
+    let notification = Notification {
+        title: &entry.title,
+        site: Some(&entry.site),
+        url: Some(&entry.url),
+        url_title: entry.url_title.as_deref(),
+        priority: entry.priority,
+        message: &entry.message,
+        score: entry.score,
+        merch: entry.merch,
+        sound: entry.sound.as_deref(),
+        image: entry.image.as_ref(),
+        previous_image: entry.previous_image.as_ref(),
+    };
+
+    NOTIFIERS.get().expect("no notifiers").send(&entry.notifiers, &notification).await;
+}
+
+/// sends through the named backends, or every backend if there are none, unless notifications are silenced
+async fn send_notification(notifiers: &[String], title: &str, url: Option<&str>, url_title: Option<&str>, priority: i8, message: &str) {
+    if silenced() {
+        return;
+    }
+
+    let notification = Notification { title, site: None, url, url_title, priority, message, score: None, merch: false, sound: None, image: None, previous_image: None };
+    NOTIFIERS.get().expect("no notifiers").send(notifiers, &notification).await;
+}
+
+// sends a labeled test message through every backend and reports how each went
+async fn test_notifications() {
+    let notification = Notification {
+        title: "Test Notification",
+        site: None,
+        url: None,
+        url_title: None,
+        priority: 0,
+        message: "This is a test notification from website change notifier, if you see this it works.",
+        score: None,
+        merch: false,
+        sound: None,
+        image: None,
+        previous_image: None,
+    };
+
+    for (name, result) in NOTIFIERS.get().expect("no notifiers").send_each(&[], &notification).await {
+        match result {
+            Ok(()) => println!("{name} -> ok"),
+            Err(e) => println!("{name} -> failed {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_env_vars_everywhere() {
+        env::set_var("WEBSITE_CHANGE_NOTIFIER_TEST_TOKEN", "secret");
+        let mut config = serde_json::json!({ "token": "a ${WEBSITE_CHANGE_NOTIFIER_TEST_TOKEN} b", "sites": [{ "url": "${WEBSITE_CHANGE_NOTIFIER_TEST_TOKEN}" }] });
+
+        interpolate_env(&mut config).unwrap();
+        assert_eq!(config, serde_json::json!({ "token": "a secret b", "sites": [{ "url": "secret" }] }));
+    }
+
+    #[test]
+    fn missing_or_unclosed_env_vars_fail() {
+        assert!(interpolate_env(&mut serde_json::json!("${WEBSITE_CHANGE_NOTIFIER_TEST_UNSET}")).is_err());
+        assert!(interpolate_env(&mut serde_json::json!("${WEBSITE_CHANGE_NOTIFIER_TEST_TOKEN")).is_err());
+    }
+
+    #[test]
+    fn escaped_env_vars_and_scripts_are_left_alone() {
+        let mut config = serde_json::json!({
+            "title_template": "$${price}",
+            "sites": [{ "scripts": ["console.log(`${x}`)"], "decision_script": "return `${y}`" }],
+        });
+
+        interpolate_env(&mut config).unwrap();
+        assert_eq!(config, serde_json::json!({
+            "title_template": "${price}",
+            "sites": [{ "scripts": ["console.log(`${x}`)"], "decision_script": "return `${y}`" }],
+        }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn defaults_only_fill_in_what_sites_leave_out() {
+        let mut config = serde_json::json!({ "defaults": { "threshold": 0.9, "priority": 1 }, "sites": [{ "url": "a", "threshold": 0.5 }] });
+
+        apply_defaults(&mut config);
+        assert_eq!(config, serde_json::json!({ "sites": [{ "url": "a", "threshold": 0.5, "priority": 1 }] }));
+    }
+}
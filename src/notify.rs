@@ -0,0 +1,1790 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::env;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use futures::future::{BoxFuture, join_all};
+use futures::FutureExt;
+use hmac::{Hmac, Mac};
+use image::{ImageOutputFormat, RgbImage};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use pushover_rs::{AttachmentMessageBuilder, MessageBuilder, PushoverSound, send_pushover_request, send_pushover_request_with_attachment};
+use reqwest::multipart::{Form, Part};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use tokio::task;
+use tokio::time::sleep;
+
+use crate::compare;
+use crate::stats;
+use crate::stats::unix_now;
+
+/// one alert, the same for every backend it goes through
+pub struct Notification<'a> {
+    pub title: &'a str,
+    /// the monitored url, none for notifications about several sites or the notifier itself
+    pub site: Option<&'a str>,
+    pub url: Option<&'a str>,
+    pub url_title: Option<&'a str>,
+    pub priority: i8,
+    pub message: &'a str,
+    /// the difference rating that caused the alert
+    pub score: Option<f64>,
+    pub merch: bool,
+    /// pushover sound, the user's default if not set
+    pub sound: Option<&'a str>,
+    /// latest capture of the page, backends that can show images attach it
+    pub image: Option<&'a RgbImage>,
+    /// the baseline the latest capture was compared against
+    pub previous_image: Option<&'a RgbImage>,
+}
+
+impl Notification<'_> {
+    /// the page itself changed, rather than a notice like an expiring cert or failing checks
+    pub fn is_change(&self) -> bool {
+        self.score.is_some() || self.merch
+    }
+}
+
+/// how one page of a site compared this check
+pub struct CheckResult<'a> {
+    pub site: &'a str,
+    /// the page that was checked, the site's url when the result covers all of its pages
+    pub page: &'a str,
+    /// none for the modes that don't compare captures, like feeds
+    pub score: Option<f64>,
+    pub changed: bool,
+}
+
+/// a notification waiting to be retried, owned since the check it came from is long gone by then
+struct PendingRetry {
+    notifier: String,
+    title: String,
+    site: Option<String>,
+    url: Option<String>,
+    url_title: Option<String>,
+    priority: i8,
+    message: String,
+    score: Option<f64>,
+    merch: bool,
+    sound: Option<String>,
+    image: Option<RgbImage>,
+    previous_image: Option<RgbImage>,
+    /// sends so far, including the first one
+    attempts: u32,
+    due: Instant,
+}
+
+impl PendingRetry {
+    fn new(notifier: &str, notification: &Notification<'_>) -> PendingRetry {
+        PendingRetry {
+            notifier: notifier.to_string(),
+            title: notification.title.to_string(),
+            site: notification.site.map(str::to_string),
+            url: notification.url.map(str::to_string),
+            url_title: notification.url_title.map(str::to_string),
+            priority: notification.priority,
+            message: notification.message.to_string(),
+            score: notification.score,
+            merch: notification.merch,
+            sound: notification.sound.map(str::to_string),
+            image: notification.image.cloned(),
+            previous_image: notification.previous_image.cloned(),
+            attempts: 1,
+            due: Instant::now(),
+        }
+    }
+
+    fn notification(&self) -> Notification<'_> {
+        Notification {
+            title: &self.title,
+            site: self.site.as_deref(),
+            url: self.url.as_deref(),
+            url_title: self.url_title.as_deref(),
+            priority: self.priority,
+            message: &self.message,
+            score: self.score,
+            merch: self.merch,
+            sound: self.sound.as_deref(),
+            image: self.image.as_ref(),
+            previous_image: self.previous_image.as_ref(),
+        }
+    }
+}
+
+/// how failed sends get retried, the delay doubles after every failed attempt up to the max delay
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// sends in total before giving up, 1 means no retries
+    attempts: u32,
+    /// seconds before the first retry
+    delay: u64,
+    /// cap on the seconds between two retries
+    max_delay: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig { attempts: 5, delay: 30, max_delay: 1800 }
+    }
+}
+
+impl RetryConfig {
+    fn delay_after(&self, attempts: u32) -> Duration {
+        let delay = self.delay.saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)));
+        Duration::from_secs(delay.min(self.max_delay))
+    }
+}
+
+/// cap on notifications across every site, the ones over it get collapsed into a summary
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// notifications allowed per window
+    max: usize,
+    /// length of the window in seconds
+    #[serde(default = "RateLimitConfig::default_per")]
+    per: u64,
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) {
+        if self.max == 0 {
+            panic!("rate limit max has to be at least 1")
+        }
+    }
+
+    fn default_per() -> u64 {
+        3600
+    }
+}
+
+/// something that can deliver a notification, like pushover
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// background work between sends, like following up on unacknowledged alerts
+    fn poll(&self) -> BoxFuture<'_, ()> {
+        async {}.boxed()
+    }
+
+    /// the result of every check, changed or not, for backends that want to publish them
+    fn check_result<'a>(&'a self, _result: &'a CheckResult<'a>) -> BoxFuture<'a, ()> {
+        async {}.boxed()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    Pushover {
+        /// falls back to the PUSHOVER_USER_KEY env var
+        user_key: Option<String>,
+        /// falls back to the PUSHOVER_APP_TOKEN env var
+        app_token: Option<String>,
+        /// only alert these devices of the user, every device if empty
+        #[serde(default)]
+        devices: Vec<String>,
+        #[serde(default)]
+        attach: PushoverAttachment,
+        /// send merch detections as emergency priority, repeating until acknowledged
+        emergency: Option<EmergencyConfig>,
+    },
+    /// posts to a channel, make one per channel & pick them per site
+    Discord {
+        webhook_url: String,
+        /// attach the latest capture to the message
+        #[serde(default = "NotifierConfig::default_attach_image")]
+        attach_image: bool,
+    },
+    /// messages a chat through a bot, the capture is sent as a photo with the message as its caption
+    Telegram {
+        /// falls back to the TELEGRAM_BOT_TOKEN env var
+        bot_token: Option<String>,
+        /// falls back to the TELEGRAM_CHAT_ID env var
+        chat_id: Option<String>,
+    },
+    /// emails the message with the before & after captures attached
+    Smtp {
+        host: String,
+        /// the default port of the security mode if not set
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        /// falls back to the SMTP_PASSWORD env var
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// posts to a room, with the capture uploaded as an image event
+    Matrix {
+        /// like https://matrix.org
+        homeserver: String,
+        /// like !abcdef:matrix.org
+        room_id: String,
+        /// falls back to the MATRIX_ACCESS_TOKEN env var
+        access_token: Option<String>,
+    },
+    /// posts block formatted messages to a slack incoming webhook
+    Slack {
+        webhook_url: String,
+    },
+    /// pushes to a self hosted gotify server
+    Gotify {
+        /// like https://gotify.example.com
+        url: String,
+        /// app token, falls back to the GOTIFY_TOKEN env var
+        token: Option<String>,
+        /// gotify priority of changes
+        #[serde(default = "NotifierConfig::default_gotify_priority")]
+        priority: u8,
+        /// gotify priority of merch detections
+        #[serde(default = "NotifierConfig::default_gotify_merch_priority")]
+        merch_priority: u8,
+        /// gotify priority of the low priority heads ups
+        #[serde(default = "NotifierConfig::default_gotify_low_priority")]
+        low_priority: u8,
+    },
+    /// publishes the json events of the webhook to {topic}/change, for home automation
+    Mqtt {
+        host: String,
+        #[serde(default = "NotifierConfig::default_mqtt_port")]
+        port: u16,
+        #[serde(default = "NotifierConfig::default_mqtt_topic")]
+        topic: String,
+        username: Option<String>,
+        /// falls back to the MQTT_PASSWORD env var
+        password: Option<String>,
+        /// also publish every check's url, score & if it changed to {topic}/check
+        #[serde(default)]
+        publish_checks: bool,
+        #[serde(default)]
+        retain: bool,
+    },
+    /// native desktop notification with the capture as the icon, for running on a workstation
+    Desktop,
+    /// texts through twilio, only sites that list it in their notifiers burn credits
+    Twilio {
+        /// falls back to the TWILIO_ACCOUNT_SID env var
+        account_sid: Option<String>,
+        /// falls back to the TWILIO_AUTH_TOKEN env var
+        auth_token: Option<String>,
+        /// twilio number the texts come from
+        from: String,
+        to: Vec<String>,
+        /// only text merch detections
+        #[serde(default = "NotifierConfig::default_merch_only")]
+        merch_only: bool,
+    },
+    /// opens a pagerduty incident per changed site, resolved once its checks stop seeing changes
+    Pagerduty {
+        /// events v2 integration key, falls back to the PAGERDUTY_ROUTING_KEY env var
+        routing_key: Option<String>,
+    },
+    /// opens an opsgenie alert per changed site, closed once its checks stop seeing changes
+    Opsgenie {
+        /// falls back to the OPSGENIE_API_KEY env var
+        api_key: Option<String>,
+        /// the account is in the eu region
+        #[serde(default)]
+        eu: bool,
+    },
+    /// posts the alert as a json event, for plugging into other automation
+    Webhook {
+        url: String,
+        /// sign the body with hmac sha256 of this, sent hex encoded in the X-Signature-256 header
+        secret: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct EmergencyConfig {
+    /// seconds between pushover's repeats of the alert, at least 30
+    #[serde(default = "EmergencyConfig::default_retry")]
+    retry: u64,
+    /// seconds pushover keeps repeating it for, at most 10800
+    #[serde(default = "EmergencyConfig::default_expire")]
+    expire: u64,
+    /// how many times to send it again when it expired without being acknowledged
+    #[serde(default = "EmergencyConfig::default_renotify")]
+    renotify: u32,
+}
+
+impl EmergencyConfig {
+    fn default_retry() -> u64 {
+        60
+    }
+
+    fn default_expire() -> u64 {
+        3600
+    }
+
+    fn default_renotify() -> u32 {
+        1
+    }
+}
+
+/// an emergency alert that hasn't been acknowledged yet
+struct Receipt {
+    id: String,
+    title: String,
+    message: String,
+    url: Option<String>,
+    url_title: Option<String>,
+    sound: Option<String>,
+    renotifies_left: u32,
+    checked_at: Instant,
+}
+
+impl Receipt {
+    /// what gets sent again once the receipt expires
+    fn notification(&self) -> Notification<'_> {
+        Notification {
+            title: &self.title,
+            site: None,
+            url: self.url.as_deref(),
+            url_title: self.url_title.as_deref(),
+            priority: 2,
+            message: &self.message,
+            score: None,
+            merch: true,
+            sound: self.sound.as_deref(),
+            image: None,
+            previous_image: None,
+        }
+    }
+}
+
+/// seconds between two checks of a receipt, pushover doesn't want them polled much more often
+const RECEIPT_POLL_INTERVAL: u64 = 60;
+/// keeps the attachment files of sends running at the same time apart
+static ATTACHMENTS: AtomicU64 = AtomicU64::new(0);
+
+/// the sound called this, pushover-rs only takes its enum
+pub fn pushover_sound(name: &str) -> Option<PushoverSound> {
+    let sound = match name {
+        "pushover" => PushoverSound::PUSHOVER,
+        "bike" => PushoverSound::BIKE,
+        "bugle" => PushoverSound::BUGLE,
+        "cashregister" => PushoverSound::CASHREGISTER,
+        "classical" => PushoverSound::CLASSICAL,
+        "cosmic" => PushoverSound::COSMIC,
+        "falling" => PushoverSound::FALLING,
+        "gamelan" => PushoverSound::GAMELAN,
+        "incoming" => PushoverSound::INCOMING,
+        "intermission" => PushoverSound::INTERMISSION,
+        "magic" => PushoverSound::MAGIC,
+        "mechanical" => PushoverSound::MECHANICAL,
+        "pianobar" => PushoverSound::PIANOBAR,
+        "siren" => PushoverSound::SIREN,
+        "spacealarm" => PushoverSound::SPACEALARM,
+        "tugboat" => PushoverSound::TUGBOAT,
+        "alien" => PushoverSound::ALIEN,
+        "climb" => PushoverSound::CLIMB,
+        "persistent" => PushoverSound::PERSISTENT,
+        "echo" => PushoverSound::ECHO,
+        "updown" => PushoverSound::UPDOWN,
+        "vibrate" => PushoverSound::VIBRATE,
+        "none" => PushoverSound::NONE,
+        _ => return None,
+    };
+
+    Some(sound)
+}
+
+/// what pushover attaches, downscaled to the thumbnail width if one is set
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PushoverAttachment {
+    /// just the text
+    None,
+    /// the latest capture
+    #[default]
+    Capture,
+    /// the latest capture with what changed since the baseline in red, the capture if there's no baseline to diff
+    Diff,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// tls from the start, usually port 465
+    Tls,
+    /// upgrade a plain connection, usually port 587
+    #[default]
+    Starttls,
+    /// no encryption at all, only for relays on the same machine or network
+    None,
+}
+
+impl NotifierConfig {
+    /// the same checks as build, without building clients (mqtt would connect to the broker right away)
+    pub fn validate(&self) {
+        match self {
+            NotifierConfig::Pushover { user_key, app_token, emergency, .. } => {
+                if emergency.is_some_and(|emergency| emergency.retry < 30 || emergency.expire > 10800) {
+                    panic!("pushover emergency retry has to be >= 30 & expire <= 10800")
+                }
+
+                require_env(user_key, "PUSHOVER_USER_KEY", "pushover user key");
+                require_env(app_token, "PUSHOVER_APP_TOKEN", "pushover app token");
+            }
+            NotifierConfig::Telegram { bot_token, chat_id } => {
+                require_env(bot_token, "TELEGRAM_BOT_TOKEN", "telegram bot token");
+                require_env(chat_id, "TELEGRAM_CHAT_ID", "telegram chat id");
+            }
+            NotifierConfig::Smtp { username, password, from, to, .. } => {
+                if username.is_some() {
+                    require_env(password, "SMTP_PASSWORD", "smtp password");
+                }
+
+                if to.is_empty() {
+                    panic!("smtp notifier needs at least one to address")
+                }
+
+                from.parse::<lettre::message::Mailbox>().expect("invalid smtp from address");
+                for to in to {
+                    to.parse::<lettre::message::Mailbox>().expect("invalid smtp to address");
+                }
+            }
+            NotifierConfig::Matrix { access_token, .. } => require_env(access_token, "MATRIX_ACCESS_TOKEN", "matrix access token"),
+            NotifierConfig::Gotify { token, .. } => require_env(token, "GOTIFY_TOKEN", "gotify token"),
+            NotifierConfig::Mqtt { username, password, .. } => {
+                if username.is_some() {
+                    require_env(password, "MQTT_PASSWORD", "mqtt password");
+                }
+            }
+            NotifierConfig::Pagerduty { routing_key } => require_env(routing_key, "PAGERDUTY_ROUTING_KEY", "pagerduty routing key"),
+            NotifierConfig::Opsgenie { api_key, .. } => require_env(api_key, "OPSGENIE_API_KEY", "opsgenie api key"),
+            NotifierConfig::Twilio { account_sid, auth_token, to, .. } => {
+                if to.is_empty() {
+                    panic!("twilio notifier needs at least one to number")
+                }
+
+                require_env(account_sid, "TWILIO_ACCOUNT_SID", "twilio account sid");
+                require_env(auth_token, "TWILIO_AUTH_TOKEN", "twilio auth token");
+            }
+            NotifierConfig::Discord { .. } | NotifierConfig::Slack { .. } | NotifierConfig::Desktop | NotifierConfig::Webhook { .. } => {}
+        }
+    }
+
+    pub fn build(self, thumbnail_width: Option<u32>) -> Box<dyn Notifier> {
+        match self {
+            NotifierConfig::Pushover { user_key, app_token, devices, attach, emergency } => Box::new(Pushover {
+                client: reqwest::Client::new(),
+                emergency: emergency.inspect(|emergency| {
+                    if emergency.retry < 30 || emergency.expire > 10800 {
+                        panic!("pushover emergency retry has to be >= 30 & expire <= 10800")
+                    }
+                }),
+                receipts: Mutex::default(),
+                user_key: user_key.unwrap_or_else(|| env::var("PUSHOVER_USER_KEY").expect("no pushover user key env var")),
+                app_token: app_token.unwrap_or_else(|| env::var("PUSHOVER_APP_TOKEN").expect("no pushover app token env var")),
+                devices,
+                attach,
+                thumbnail_width,
+            }),
+            NotifierConfig::Discord { webhook_url, attach_image } => Box::new(Discord {
+                client: reqwest::Client::new(),
+                webhook_url,
+                attach_image,
+            }),
+            NotifierConfig::Telegram { bot_token, chat_id } => Box::new(Telegram {
+                client: reqwest::Client::new(),
+                bot_token: bot_token.unwrap_or_else(|| env::var("TELEGRAM_BOT_TOKEN").expect("no telegram bot token env var")),
+                chat_id: chat_id.unwrap_or_else(|| env::var("TELEGRAM_CHAT_ID").expect("no telegram chat id env var")),
+            }),
+            NotifierConfig::Smtp { host, port, security, username, password, from, to } => {
+                let mut transport = match security {
+                    SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&host).expect("invalid smtp host"),
+                    SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host).expect("invalid smtp host"),
+                    SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+                };
+
+                if let Some(port) = port {
+                    transport = transport.port(port);
+                }
+
+                if let Some(username) = username {
+                    let password = password.unwrap_or_else(|| env::var("SMTP_PASSWORD").expect("no smtp password env var"));
+                    transport = transport.credentials(Credentials::new(username, password));
+                }
+
+                if to.is_empty() {
+                    panic!("smtp notifier needs at least one to address")
+                }
+
+                Box::new(Smtp {
+                    transport: transport.build(),
+                    from: from.parse().expect("invalid smtp from address"),
+                    to: to.iter().map(|to| to.parse().expect("invalid smtp to address")).collect(),
+                })
+            }
+            NotifierConfig::Matrix { homeserver, room_id, access_token } => Box::new(Matrix {
+                client: reqwest::Client::new(),
+                homeserver: homeserver.trim_end_matches('/').to_string(),
+                room_id,
+                access_token: access_token.unwrap_or_else(|| env::var("MATRIX_ACCESS_TOKEN").expect("no matrix access token env var")),
+                transactions: AtomicU64::new(0),
+            }),
+            NotifierConfig::Slack { webhook_url } => Box::new(Slack {
+                client: reqwest::Client::new(),
+                webhook_url,
+            }),
+            NotifierConfig::Gotify { url, token, priority, merch_priority, low_priority } => Box::new(Gotify {
+                client: reqwest::Client::new(),
+                url: url.trim_end_matches('/').to_string(),
+                token: token.unwrap_or_else(|| env::var("GOTIFY_TOKEN").expect("no gotify token env var")),
+                priority,
+                merch_priority,
+                low_priority,
+            }),
+            NotifierConfig::Mqtt { host, port, topic, username, password, publish_checks, retain } => {
+                let mut options = MqttOptions::new(format!("website-change-notifier-{}", std::process::id()), host, port);
+                options.set_keep_alive(Duration::from_secs(30));
+
+                if let Some(username) = username {
+                    let password = password.unwrap_or_else(|| env::var("MQTT_PASSWORD").expect("no mqtt password env var"));
+                    options.set_credentials(username, password);
+                }
+
+                let (client, mut event_loop) = AsyncClient::new(options, 32);
+
+                // the event loop is what actually talks to the broker, it reconnects on its own when polled again
+                task::spawn(async move {
+                    loop {
+                        if let Err(e) = event_loop.poll().await {
+                            eprintln!("Mqtt connection error -> {e:?}");
+                            sleep(Duration::from_secs(5)).await;
+                        }
+                    }
+                });
+
+                Box::new(Mqtt { client, topic: topic.trim_end_matches('/').to_string(), publish_checks, retain })
+            }
+            NotifierConfig::Desktop => Box::new(Desktop),
+            NotifierConfig::Pagerduty { routing_key } => Box::new(Pagerduty {
+                client: reqwest::Client::new(),
+                routing_key: routing_key.unwrap_or_else(|| env::var("PAGERDUTY_ROUTING_KEY").expect("no pagerduty routing key env var")),
+                open: Mutex::default(),
+            }),
+            NotifierConfig::Opsgenie { api_key, eu } => Box::new(Opsgenie {
+                client: reqwest::Client::new(),
+                api_key: api_key.unwrap_or_else(|| env::var("OPSGENIE_API_KEY").expect("no opsgenie api key env var")),
+                api_url: if eu { "https://api.eu.opsgenie.com" } else { "https://api.opsgenie.com" },
+                open: Mutex::default(),
+            }),
+            NotifierConfig::Twilio { account_sid, auth_token, from, to, merch_only } => {
+                if to.is_empty() {
+                    panic!("twilio notifier needs at least one to number")
+                }
+
+                Box::new(Twilio {
+                    client: reqwest::Client::new(),
+                    account_sid: account_sid.unwrap_or_else(|| env::var("TWILIO_ACCOUNT_SID").expect("no twilio account sid env var")),
+                    auth_token: auth_token.unwrap_or_else(|| env::var("TWILIO_AUTH_TOKEN").expect("no twilio auth token env var")),
+                    from,
+                    to,
+                    merch_only,
+                })
+            }
+            NotifierConfig::Webhook { url, secret } => Box::new(Webhook {
+                client: reqwest::Client::new(),
+                url,
+                secret,
+            }),
+        }
+    }
+
+    fn default_attach_image() -> bool {
+        true
+    }
+
+    fn default_merch_only() -> bool {
+        true
+    }
+
+    fn default_mqtt_port() -> u16 {
+        1883
+    }
+
+    fn default_mqtt_topic() -> String {
+        String::from("website-change-notifier")
+    }
+
+    fn default_gotify_priority() -> u8 {
+        5
+    }
+
+    fn default_gotify_merch_priority() -> u8 {
+        8
+    }
+
+    fn default_gotify_low_priority() -> u8 {
+        2
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuietHoursConfig {
+    /// local time they start, like 23:00
+    start: String,
+    /// local time they end, like 07:00
+    end: String,
+    /// iana name like Europe/Berlin, utc if not set
+    timezone: Option<String>,
+    /// merch detections still go out during quiet hours
+    #[serde(default = "QuietHoursConfig::default_true")]
+    merch_bypass: bool,
+    /// hold the notifications & send them as one digest when the quiet hours end, otherwise they're dropped
+    #[serde(default = "QuietHoursConfig::default_true")]
+    queue: bool,
+}
+
+impl QuietHoursConfig {
+    fn default_true() -> bool {
+        true
+    }
+
+    pub fn build(self) -> QuietHours {
+        let parse_time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M")
+            .unwrap_or_else(|_| panic!("quiet hours time {time} isn't HH:MM"));
+
+        QuietHours {
+            start: parse_time(&self.start),
+            end: parse_time(&self.end),
+            timezone: self.timezone
+                .map(|timezone| timezone.parse().unwrap_or_else(|_| panic!("unknown quiet hours timezone {timezone}")))
+                .unwrap_or(Tz::UTC),
+            merch_bypass: self.merch_bypass,
+            queue: self.queue,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    timezone: Tz,
+    merch_bypass: bool,
+    queue: bool,
+}
+
+impl QuietHours {
+    /// windows can wrap around midnight, like 23:00 to 07:00
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.timezone).time();
+
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn merch_bypass(&self) -> bool {
+        self.merch_bypass
+    }
+
+    pub fn queue(&self) -> bool {
+        self.queue
+    }
+}
+
+/// every configured backend by name, sends a notification through any number of them at once
+pub struct Fanout {
+    notifiers: Vec<(String, Box<dyn Notifier>)>,
+    retry: RetryConfig,
+    pending: Mutex<Vec<PendingRetry>>,
+    rate_limit: Option<RateLimitConfig>,
+    /// when the notifications in the current rate limit window went out
+    sent: Mutex<VecDeque<Instant>>,
+    /// the notifications the rate limit held back, as title & message
+    overflow: Mutex<Vec<(String, String)>>,
+}
+
+impl Fanout {
+    /// without any notifiers configured it's just pushover from the env vars, like before backends were configurable
+    pub fn new(
+        configs: BTreeMap<String, NotifierConfig>,
+        thumbnail_width: Option<u32>,
+        retry: RetryConfig,
+        rate_limit: Option<RateLimitConfig>,
+    ) -> Fanout {
+        let notifiers = Fanout::with_default(configs).into_iter()
+            .map(|(name, config)| (name, config.build(thumbnail_width)))
+            .collect();
+
+        if let Some(rate_limit) = rate_limit {
+            rate_limit.validate();
+        }
+
+        Fanout {
+            notifiers,
+            retry,
+            pending: Mutex::default(),
+            rate_limit,
+            sent: Mutex::default(),
+            overflow: Mutex::default(),
+        }
+    }
+
+    /// pushover from the env vars when no notifiers are configured
+    pub fn with_default(mut configs: BTreeMap<String, NotifierConfig>) -> BTreeMap<String, NotifierConfig> {
+        if configs.is_empty() {
+            configs.insert(String::from("pushover"), NotifierConfig::Pushover { user_key: None, app_token: None, devices: vec![], attach: PushoverAttachment::default(), emergency: None });
+        }
+
+        configs
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.notifiers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// sends through the named backends, or all of them when none are named, failures are queued for a retry
+    pub async fn send(&self, names: &[String], notification: &Notification<'_>) {
+        if !self.take_rate_limit_slot() {
+            println!("Rate limit reached, holding back {}", notification.title);
+            self.overflow.lock().unwrap().push((notification.title.to_string(), notification.message.to_string()));
+            return;
+        }
+
+        self.deliver(names, notification).await;
+    }
+
+    async fn deliver(&self, names: &[String], notification: &Notification<'_>) {
+        for (name, result) in self.send_each(names, notification).await {
+            if let Err(e) = result {
+                eprintln!("Error sending message through {name} {e:?}");
+                self.schedule_retry(PendingRetry::new(name, notification));
+            }
+        }
+    }
+
+    /// hands a check result to the named backends, or all of them when none are named
+    pub async fn check_result(&self, names: &[String], result: &CheckResult<'_>) {
+        let selected = self.notifiers.iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(name));
+
+        join_all(selected.map(|(_, notifier)| notifier.check_result(result))).await;
+    }
+
+    /// background work of the notifiers & the retries that are due, run this every few seconds
+    pub async fn poll(&self) {
+        join_all(self.notifiers.iter().map(|(_, notifier)| notifier.poll())).await;
+        self.retry_due().await;
+        self.send_overflow().await;
+    }
+
+    /// counts a notification against the rate limit, false if it's used up
+    fn take_rate_limit_slot(&self) -> bool {
+        let Some(rate_limit) = self.rate_limit else {
+            return true;
+        };
+
+        let mut sent = self.sent.lock().unwrap();
+        while sent.front().is_some_and(|at| at.elapsed() >= Duration::from_secs(rate_limit.per)) {
+            sent.pop_front();
+        }
+
+        if sent.len() >= rate_limit.max {
+            return false;
+        }
+
+        sent.push_back(Instant::now());
+        true
+    }
+
+    // one summary of everything the rate limit held back, once there's room again
+    async fn send_overflow(&self) {
+        if self.overflow.lock().unwrap().is_empty() || !self.take_rate_limit_slot() {
+            return;
+        }
+
+        let overflow = std::mem::take(&mut *self.overflow.lock().unwrap());
+        let message = overflow.iter()
+            .map(|(title, message)| format!("{title}: {}", message.lines().next().unwrap_or_default()))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let notification = Notification {
+            title: &format!("{} Notifications Held Back", overflow.len()),
+            site: None,
+            url: None,
+            url_title: None,
+            priority: 0,
+            message: &message,
+            score: None,
+            merch: false,
+            sound: None,
+            image: None,
+            previous_image: None,
+        };
+
+        println!("Sending the {} notifications the rate limit held back...", overflow.len());
+        self.deliver(&[], &notification).await;
+    }
+
+    async fn retry_due(&self) {
+        let due = {
+            let mut pending = self.pending.lock().unwrap();
+            let (due, waiting) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition::<Vec<PendingRetry>, _>(|retry| retry.due <= Instant::now());
+
+            *pending = waiting;
+            due
+        };
+
+        for mut retry in due {
+            let Some((_, notifier)) = self.notifiers.iter().find(|(name, _)| *name == retry.notifier) else {
+                continue;
+            };
+
+            match notifier.send(&retry.notification()).await {
+                Ok(()) => println!("Sent message through {} on attempt {}", retry.notifier, retry.attempts + 1),
+                Err(e) => {
+                    eprintln!("Error retrying message through {} {e:?}", retry.notifier);
+                    retry.attempts += 1;
+                    self.schedule_retry(retry);
+                }
+            }
+        }
+    }
+
+    fn schedule_retry(&self, mut retry: PendingRetry) {
+        if retry.attempts >= self.retry.attempts {
+            if self.retry.attempts > 1 {
+                eprintln!("Giving up on message through {} after {} attempts -> {}", retry.notifier, retry.attempts, retry.message);
+            }
+
+            return;
+        }
+
+        let delay = self.retry.delay_after(retry.attempts);
+        println!("Retrying message through {} in {}s", retry.notifier, delay.as_secs());
+
+        retry.due = Instant::now() + delay;
+        self.pending.lock().unwrap().push(retry);
+    }
+
+    /// like send but hands back how every backend went
+    pub async fn send_each(&self, names: &[String], notification: &Notification<'_>) -> Vec<(&str, anyhow::Result<()>)> {
+        let selected = self.notifiers.iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(name));
+
+        join_all(selected.map(|(name, notifier)| {
+            notifier.send(notification).map(move |result| (name.as_str(), result))
+        })).await
+    }
+}
+
+struct Pushover {
+    client: reqwest::Client,
+    user_key: String,
+    app_token: String,
+    devices: Vec<String>,
+    attach: PushoverAttachment,
+    /// downscale attachments to this width
+    thumbnail_width: Option<u32>,
+    emergency: Option<EmergencyConfig>,
+    receipts: Mutex<Vec<Receipt>>,
+}
+
+impl Notifier for Pushover {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            if let Some(emergency) = self.emergency.filter(|_| notification.merch) {
+                return self.send_emergency(emergency, notification, emergency.renotify).await;
+            }
+
+            let attachment = match (self.attach, notification.image, notification.previous_image) {
+                (PushoverAttachment::None, _, _) => None,
+                (PushoverAttachment::Diff, Some(image), Some(previous)) => compare::highlight_changes(previous, image)
+                    .map(Cow::Owned)
+                    .or(Some(Cow::Borrowed(image))),
+                (_, image, _) => image.map(Cow::Borrowed),
+            };
+
+            if let (Some(image), Some(url)) = (attachment, notification.url) {
+                match self.send_attachment(notification, url, &image).await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => eprintln!("Error sending attachment, sending without it -> {e:?}"),
+                }
+            }
+
+            self.send_plain(notification).await
+        }.boxed()
+    }
+
+    fn poll(&self) -> BoxFuture<'_, ()> {
+        async move {
+            let Some(emergency) = self.emergency else {
+                return;
+            };
+
+            let due = {
+                let mut receipts = self.receipts.lock().unwrap();
+                let (due, waiting) = std::mem::take(&mut *receipts)
+                    .into_iter()
+                    .partition::<Vec<Receipt>, _>(|receipt| receipt.checked_at.elapsed() >= Duration::from_secs(RECEIPT_POLL_INTERVAL));
+
+                *receipts = waiting;
+                due
+            };
+
+            for mut receipt in due {
+                receipt.checked_at = Instant::now();
+
+                let status = match self.receipt_status(&receipt.id).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("Error checking pushover receipt {} -> {e:?}", receipt.id);
+                        self.receipts.lock().unwrap().push(receipt);
+                        continue;
+                    }
+                };
+
+                match status {
+                    ReceiptStatus::Acknowledged => println!("Emergency alert {} was acknowledged", receipt.id),
+                    ReceiptStatus::Pending => self.receipts.lock().unwrap().push(receipt),
+                    ReceiptStatus::Expired if receipt.renotifies_left == 0 => {
+                        eprintln!("Emergency alert {} expired without being acknowledged", receipt.id);
+                    }
+                    ReceiptStatus::Expired => {
+                        println!("Emergency alert {} expired without being acknowledged, sending it again", receipt.id);
+
+                        let result = self.send_emergency(emergency, &receipt.notification(), receipt.renotifies_left - 1).await;
+
+                        if let Err(e) = result {
+                            eprintln!("Error sending emergency alert again -> {e:?}");
+                        }
+                    }
+                }
+            }
+        }.boxed()
+    }
+}
+
+enum ReceiptStatus {
+    Pending,
+    Acknowledged,
+    Expired,
+}
+
+impl Pushover {
+    // pushover-rs can't do emergency priority, it needs retry & expire and hands back a receipt
+    async fn send_emergency(
+        &self,
+        emergency: EmergencyConfig,
+        notification: &Notification<'_>,
+        renotifies_left: u32,
+    ) -> anyhow::Result<()> {
+        let retry = emergency.retry.to_string();
+        let expire = emergency.expire.to_string();
+        let devices = self.devices.join(",");
+
+        let mut form = vec![
+            ("token", self.app_token.as_str()),
+            ("user", self.user_key.as_str()),
+            ("title", notification.title),
+            ("message", notification.message),
+            ("priority", "2"),
+            ("retry", retry.as_str()),
+            ("expire", expire.as_str()),
+        ];
+
+        if let Some(url) = notification.url {
+            form.push(("url", url));
+        }
+
+        if let Some(url_title) = notification.url_title {
+            form.push(("url_title", url_title));
+        }
+
+        if !devices.is_empty() {
+            form.push(("device", devices.as_str()));
+        }
+
+        if let Some(sound) = notification.sound {
+            form.push(("sound", sound));
+        }
+
+        let response = self.client.post("https://api.pushover.net/1/messages.json")
+            .form(&form)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+
+        let Some(id) = response["receipt"].as_str() else {
+            anyhow::bail!("pushover rejected the emergency alert {:?}", response["errors"]);
+        };
+
+        println!("Sent emergency alert {id}");
+        self.receipts.lock().unwrap().push(Receipt {
+            id: id.to_string(),
+            title: notification.title.to_string(),
+            message: notification.message.to_string(),
+            url: notification.url.map(str::to_string),
+            url_title: notification.url_title.map(str::to_string),
+            sound: notification.sound.map(str::to_string),
+            renotifies_left,
+            checked_at: Instant::now(),
+        });
+
+        Ok(())
+    }
+
+    async fn receipt_status(&self, id: &str) -> anyhow::Result<ReceiptStatus> {
+        let response = self.client.get(format!("https://api.pushover.net/1/receipts/{id}.json"))
+            .query(&[("token", &self.app_token)])
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+
+        if response["status"] != 1 {
+            anyhow::bail!("pushover rejected the receipt check {:?}", response["errors"]);
+        }
+
+        Ok(if response["acknowledged"] == 1 {
+            ReceiptStatus::Acknowledged
+        } else if response["expired"] == 1 {
+            ReceiptStatus::Expired
+        } else {
+            ReceiptStatus::Pending
+        })
+    }
+
+    fn devices(&self) -> Vec<&str> {
+        self.devices.iter().map(String::as_str).collect()
+    }
+
+    async fn send_plain(&self, notification: &Notification<'_>) -> anyhow::Result<()> {
+        let mut message = MessageBuilder::new(&self.user_key, &self.app_token, notification.message)
+            .set_title(notification.title)
+            .set_priority(notification.priority);
+
+        if let Some(sound) = notification.sound.and_then(pushover_sound) {
+            message = message.set_sound(sound);
+        }
+
+        if let Some(url) = notification.url {
+            message = message.set_url(url, notification.url_title);
+        }
+
+        if !self.devices.is_empty() {
+            message = message.set_devices(self.devices());
+        }
+
+        let response = send_pushover_request(message.build())
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        if response.status != 1 {
+            anyhow::bail!("pushover rejected the message {:?}", response.errors.unwrap_or_default());
+        }
+
+        Ok(())
+    }
+
+    // attaches the capture so the change can be judged from the phone
+    async fn send_attachment(&self, notification: &Notification<'_>, url: &str, image: &RgbImage) -> anyhow::Result<()> {
+        let attachment = ATTACHMENTS.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("website-change-notifier-{}-{attachment}-{}.jpg", std::process::id(), stats::state_key(url)));
+
+        // encoding a full page capture takes a while
+        let (image, thumbnail_width, save_path) = (image.clone(), self.thumbnail_width, path.clone());
+        task::spawn_blocking(move || match thumbnail_width {
+            Some(width) => {
+                let height = (image.height() as u64 * width as u64 / image.width().max(1) as u64).max(1) as u32;
+                image::imageops::thumbnail(&image, width, height).save(&save_path)
+            }
+            None => image.save(&save_path),
+        }).await??;
+
+        let mut message = AttachmentMessageBuilder::new(&self.user_key, &self.app_token, notification.message)
+            .set_title(notification.title)
+            .set_url(url, notification.url_title)
+            .set_priority(notification.priority)
+            .set_attachment(path.to_string_lossy().to_string());
+
+        if let Some(sound) = notification.sound.and_then(pushover_sound) {
+            message = message.set_sound(sound);
+        }
+
+        if !self.devices.is_empty() {
+            message = message.set_devices(self.devices());
+        }
+
+        let message = message.build().map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        // sending attachments is blocking
+        let response = task::spawn_blocking(move || {
+            send_pushover_request_with_attachment(message).map_err(|e| e.to_string())
+        }).await;
+
+        let _ = tokio::fs::remove_file(&path).await;
+        let response = response?.map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        if response.status != 1 {
+            anyhow::bail!("pushover rejected the message {:?}", response.errors.unwrap_or_default());
+        }
+
+        Ok(())
+    }
+}
+
+struct Discord {
+    client: reqwest::Client,
+    webhook_url: String,
+    attach_image: bool,
+}
+
+impl Notifier for Discord {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut embed = json!({
+                "title": notification.title,
+                "description": notification.message,
+                "color": embed_color(notification.priority),
+            });
+
+            if let Some(url) = notification.url {
+                embed["url"] = json!(url);
+            }
+
+            let image = match notification.image {
+                Some(image) if self.attach_image => Some(png(image).await?),
+                _ => None,
+            };
+
+            let request = self.client.post(&self.webhook_url);
+            let request = match image {
+                Some(image) => {
+                    embed["image"] = json!({ "url": "attachment://capture.png" });
+
+                    let form = Form::new()
+                        .text("payload_json", json!({ "embeds": [embed] }).to_string())
+                        .part("files[0]", Part::bytes(image).file_name("capture.png").mime_str("image/png")?);
+                    request.multipart(form)
+                }
+                None => request.json(&json!({ "embeds": [embed] })),
+            };
+
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }.boxed()
+    }
+}
+
+// a secret either comes from the config or its env var
+fn require_env(value: &Option<String>, var: &str, what: &str) {
+    if value.is_none() && env::var(var).is_err() {
+        panic!("no {what} env var")
+    }
+}
+
+/// telegram limits photo captions to this many characters
+const TELEGRAM_CAPTION_LIMIT: usize = 1024;
+
+struct Telegram {
+    client: reqwest::Client,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl Notifier for Telegram {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut text = format!("{}\n{}", notification.title, notification.message);
+            if let Some(url) = notification.url {
+                text = format!("{text}\n{url}");
+            }
+
+            let request = match notification.image {
+                // too long captions get rejected, those go as a plain message instead
+                Some(image) if text.chars().count() <= TELEGRAM_CAPTION_LIMIT => {
+                    let form = Form::new()
+                        .text("chat_id", self.chat_id.clone())
+                        .text("caption", text)
+                        .part("photo", Part::bytes(png(image).await?).file_name("capture.png").mime_str("image/png")?);
+
+                    self.client.post(self.api_url("sendPhoto")).multipart(form)
+                }
+                _ => self.client.post(self.api_url("sendMessage")).json(&json!({ "chat_id": self.chat_id, "text": text })),
+            };
+
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }.boxed()
+    }
+}
+
+impl Telegram {
+    fn api_url(&self, method: &str) -> String {
+        format!("https://api.telegram.org/bot{}/{method}", self.bot_token)
+    }
+}
+
+struct Smtp {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: lettre::message::Mailbox,
+    to: Vec<lettre::message::Mailbox>,
+}
+
+impl Notifier for Smtp {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut body = notification.message.to_string();
+            if let Some(url) = notification.url {
+                body = format!("{body}\n\n{url}");
+            }
+
+            let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(body));
+            for (name, image) in [("before.png", notification.previous_image), ("after.png", notification.image)] {
+                if let Some(image) = image {
+                    parts = parts.singlepart(Attachment::new(name.to_string()).body(png(image).await?, ContentType::parse("image/png")?));
+                }
+            }
+
+            let mut email = Message::builder()
+                .from(self.from.clone())
+                .subject(notification.title);
+            for to in &self.to {
+                email = email.to(to.clone());
+            }
+
+            self.transport.send(email.multipart(parts)?).await?;
+            Ok(())
+        }.boxed()
+    }
+}
+
+struct Matrix {
+    client: reqwest::Client,
+    homeserver: String,
+    room_id: String,
+    access_token: String,
+    /// events need an id that's unique for the access token
+    transactions: AtomicU64,
+}
+
+impl Notifier for Matrix {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut body = format!("{}\n{}", notification.title, notification.message);
+            if let Some(url) = notification.url {
+                body = format!("{body}\n{url}");
+            }
+
+            self.send_event(json!({ "msgtype": "m.text", "body": body })).await?;
+
+            if let Some(image) = notification.image {
+                let bytes = png(image).await?;
+                let size = bytes.len();
+
+                let upload = self.client.post(format!("{}/_matrix/media/v3/upload", self.homeserver))
+                    .query(&[("filename", "capture.png")])
+                    .bearer_auth(&self.access_token)
+                    .header(reqwest::header::CONTENT_TYPE, "image/png")
+                    .body(bytes)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<serde_json::Value>()
+                    .await?;
+
+                let Some(content_uri) = upload["content_uri"].as_str() else {
+                    anyhow::bail!("matrix upload didn't return a content uri {upload:?}");
+                };
+
+                self.send_event(json!({
+                    "msgtype": "m.image",
+                    "body": "capture.png",
+                    "url": content_uri,
+                    "info": { "mimetype": "image/png", "w": image.width(), "h": image.height(), "size": size },
+                })).await?;
+            }
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+impl Matrix {
+    async fn send_event(&self, content: serde_json::Value) -> anyhow::Result<()> {
+        let transaction = format!("{}-{}", unix_now(), self.transactions.fetch_add(1, Ordering::Relaxed));
+        let room_id = url::form_urlencoded::byte_serialize(self.room_id.as_bytes()).collect::<String>();
+
+        self.client.put(format!("{}/_matrix/client/v3/rooms/{room_id}/send/m.room.message/{transaction}", self.homeserver))
+            .bearer_auth(&self.access_token)
+            .json(&content)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+struct Slack {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+impl Notifier for Slack {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut blocks = vec![
+                json!({ "type": "header", "text": { "type": "plain_text", "text": notification.title } }),
+                // slack rejects section texts over 3000 characters
+                json!({ "type": "section", "text": { "type": "mrkdwn", "text": notification.message.chars().take(3000).collect::<String>() } }),
+            ];
+
+            let mut fields = vec![];
+            if let Some(url) = notification.url {
+                let title = notification.url_title.unwrap_or(url);
+                fields.push(json!({ "type": "mrkdwn", "text": format!("*Site*\n<{url}|{title}>") }));
+            }
+
+            if let Some(score) = notification.score {
+                fields.push(json!({ "type": "mrkdwn", "text": format!("*Score*\n{score:.4}") }));
+            }
+
+            if notification.merch {
+                fields.push(json!({ "type": "mrkdwn", "text": "*Merch*\n:rotating_light: detected" }));
+            }
+
+            if !fields.is_empty() {
+                blocks.push(json!({ "type": "section", "fields": fields }));
+            }
+
+            // the message text is the fallback for notifications & clients without blocks
+            let payload = json!({
+                "text": format!("{}: {}", notification.title, notification.message),
+                "blocks": blocks,
+            });
+
+            self.client.post(&self.webhook_url)
+                .json(&payload)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+struct Gotify {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    priority: u8,
+    merch_priority: u8,
+    low_priority: u8,
+}
+
+impl Notifier for Gotify {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let priority = if notification.merch {
+                self.merch_priority
+            } else if notification.priority < 0 {
+                self.low_priority
+            } else {
+                self.priority
+            };
+
+            let mut message = json!({
+                "title": notification.title,
+                "message": notification.message,
+                "priority": priority,
+            });
+
+            // opens the url when the notification is tapped
+            if let Some(url) = notification.url {
+                message["extras"] = json!({ "client::notification": { "click": { "url": url } } });
+            }
+
+            self.client.post(format!("{}/message", self.url))
+                .header("X-Gotify-Key", &self.token)
+                .json(&message)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+struct Mqtt {
+    client: AsyncClient,
+    topic: String,
+    publish_checks: bool,
+    retain: bool,
+}
+
+impl Notifier for Mqtt {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            self.client.publish(format!("{}/change", self.topic), QoS::AtLeastOnce, self.retain, event_json(notification).to_string()).await?;
+            Ok(())
+        }.boxed()
+    }
+
+    fn check_result<'a>(&'a self, result: &'a CheckResult<'a>) -> BoxFuture<'a, ()> {
+        async move {
+            if !self.publish_checks {
+                return;
+            }
+
+            let payload = json!({
+                "site": result.site,
+                "page": result.page,
+                "score": result.score,
+                "changed": result.changed,
+                "timestamp": unix_now(),
+            });
+            if let Err(e) = self.client.publish(format!("{}/check", self.topic), QoS::AtMostOnce, self.retain, payload.to_string()).await {
+                eprintln!("Error publishing check result -> {e:?}");
+            }
+        }.boxed()
+    }
+}
+
+/// width of the capture shown as the icon of desktop notifications
+const DESKTOP_ICON_WIDTH: u32 = 256;
+
+struct Desktop;
+
+impl Notifier for Desktop {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut desktop = notify_rust::Notification::new();
+            desktop.summary(notification.title).body(notification.message);
+
+            // the notification daemon reads the icon after show returns, so it's left in the temp dir
+            if let (Some(image), Some(site)) = (notification.image, notification.site) {
+                let height = (image.height() as u64 * DESKTOP_ICON_WIDTH as u64 / image.width().max(1) as u64).max(1) as u32;
+                let path = env::temp_dir().join(format!("website-change-notifier-icon-{}.png", stats::state_key(site)));
+                let (image, save_path) = (image.clone(), path.clone());
+                task::spawn_blocking(move || image::imageops::thumbnail(&image, DESKTOP_ICON_WIDTH, height).save(&save_path)).await??;
+
+                desktop.icon(&path.to_string_lossy());
+            }
+
+            // showing is blocking
+            task::spawn_blocking(move || desktop.show().map(|_| ())).await??;
+            Ok(())
+        }.boxed()
+    }
+}
+
+/// texts get split into segments that are billed one by one, so long messages are cut off
+const SMS_LIMIT: usize = 320;
+
+struct Twilio {
+    client: reqwest::Client,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+    to: Vec<String>,
+    merch_only: bool,
+}
+
+impl Notifier for Twilio {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            if self.merch_only && !notification.merch {
+                return Ok(());
+            }
+
+            let mut body = format!("{}: {}", notification.title, notification.message)
+                .chars()
+                .take(SMS_LIMIT)
+                .collect::<String>();
+            if let Some(url) = notification.url {
+                body = format!("{body}\n{url}");
+            }
+
+            let url = format!("https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json", self.account_sid);
+            for to in &self.to {
+                self.client.post(&url)
+                    .basic_auth(&self.account_sid, Some(&self.auth_token))
+                    .form(&[("From", self.from.as_str()), ("To", to.as_str()), ("Body", body.as_str())])
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+struct Pagerduty {
+    client: reqwest::Client,
+    routing_key: String,
+    /// sites with an incident that isn't resolved yet
+    open: Mutex<HashSet<String>>,
+}
+
+impl Notifier for Pagerduty {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            // only changes of a site get incidents, there's nothing to resolve for the rest
+            let Some(site) = notification.site.filter(|_| notification.is_change()) else {
+                return Ok(());
+            };
+
+            let severity = if notification.merch {
+                "critical"
+            } else if notification.priority < 0 {
+                "info"
+            } else {
+                "warning"
+            };
+
+            let mut event = json!({
+                "routing_key": self.routing_key,
+                "event_action": "trigger",
+                "dedup_key": site,
+                "payload": {
+                    "summary": format!("{}: {}", notification.title, notification.message.lines().next().unwrap_or_default()),
+                    "source": site,
+                    "severity": severity,
+                    "custom_details": { "message": notification.message, "score": notification.score },
+                },
+            });
+
+            if let Some(url) = notification.url {
+                event["links"] = json!([{ "href": url, "text": notification.url_title.unwrap_or(url) }]);
+            }
+
+            self.enqueue(&event).await?;
+            self.open.lock().unwrap().insert(site.to_string());
+            Ok(())
+        }.boxed()
+    }
+
+    fn check_result<'a>(&'a self, result: &'a CheckResult<'a>) -> BoxFuture<'a, ()> {
+        async move {
+            if result.changed || !self.open.lock().unwrap().contains(result.site) {
+                return;
+            }
+
+            let event = json!({ "routing_key": self.routing_key, "event_action": "resolve", "dedup_key": result.site });
+            match self.enqueue(&event).await {
+                Ok(()) => {
+                    println!("Resolved the pagerduty incident of {}", result.site);
+                    self.open.lock().unwrap().remove(result.site);
+                }
+                Err(e) => eprintln!("Error resolving the pagerduty incident of {} -> {e:?}", result.site),
+            }
+        }.boxed()
+    }
+}
+
+impl Pagerduty {
+    async fn enqueue(&self, event: &serde_json::Value) -> anyhow::Result<()> {
+        self.client.post("https://events.pagerduty.com/v2/enqueue")
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+struct Opsgenie {
+    client: reqwest::Client,
+    api_key: String,
+    api_url: &'static str,
+    /// sites with an alert that isn't closed yet
+    open: Mutex<HashSet<String>>,
+}
+
+impl Notifier for Opsgenie {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let Some(site) = notification.site.filter(|_| notification.is_change()) else {
+                return Ok(());
+            };
+
+            let priority = if notification.merch {
+                "P1"
+            } else if notification.priority < 0 {
+                "P5"
+            } else {
+                "P3"
+            };
+
+            // opsgenie cuts the message off at 130 characters
+            let alert = json!({
+                "message": format!("{}: {site}", notification.title).chars().take(130).collect::<String>(),
+                "alias": site,
+                "description": notification.message,
+                "priority": priority,
+                "source": "website-change-notifier",
+                "details": { "url": notification.url.unwrap_or(site) },
+            });
+
+            self.client.post(format!("{}/v2/alerts", self.api_url))
+                .header(reqwest::header::AUTHORIZATION, format!("GenieKey {}", self.api_key))
+                .json(&alert)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            self.open.lock().unwrap().insert(site.to_string());
+            Ok(())
+        }.boxed()
+    }
+
+    fn check_result<'a>(&'a self, result: &'a CheckResult<'a>) -> BoxFuture<'a, ()> {
+        async move {
+            if result.changed || !self.open.lock().unwrap().contains(result.site) {
+                return;
+            }
+
+            let alias = url::form_urlencoded::byte_serialize(result.site.as_bytes()).collect::<String>();
+            let response = self.client.post(format!("{}/v2/alerts/{alias}/close", self.api_url))
+                .query(&[("identifierType", "alias")])
+                .header(reqwest::header::AUTHORIZATION, format!("GenieKey {}", self.api_key))
+                .json(&json!({ "source": "website-change-notifier" }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match response {
+                Ok(_) => {
+                    println!("Closed the opsgenie alert of {}", result.site);
+                    self.open.lock().unwrap().remove(result.site);
+                }
+                Err(e) => eprintln!("Error closing the opsgenie alert of {} -> {e:?}", result.site),
+            }
+        }.boxed()
+    }
+}
+
+/// the alert as a structured event, for backends that feed other automation
+fn event_json(notification: &Notification<'_>) -> serde_json::Value {
+    json!({
+        "title": notification.title,
+        "site": notification.site,
+        "url": notification.url,
+        "priority": notification.priority,
+        "message": notification.message,
+        "score": notification.score,
+        "merch": notification.merch,
+        "timestamp": unix_now(),
+    })
+}
+
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl Notifier for Webhook {
+    fn send<'a>(&'a self, notification: &'a Notification<'a>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let body = event_json(notification).to_string();
+
+            let mut request = self.client.post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+            if let Some(secret) = &self.secret {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+                mac.update(body.as_bytes());
+
+                let signature = mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect::<String>();
+                request = request.header("X-Signature-256", format!("sha256={signature}"));
+            }
+
+            request.body(body).send().await?.error_for_status()?;
+            Ok(())
+        }.boxed()
+    }
+}
+
+/// red for anything above normal priority, grey for the low priority heads ups
+fn embed_color(priority: i8) -> u32 {
+    match priority {
+        p if p > 0 => 0xe74c3c,
+        0 => 0x3498db,
+        _ => 0x95a5a6,
+    }
+}
+
+/// encoding a full page capture takes a while, so it's kept off the runtime
+async fn png(image: &RgbImage) -> anyhow::Result<Vec<u8>> {
+    let image = image.clone();
+    task::spawn_blocking(move || {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)?;
+        Ok(bytes)
+    }).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet_hours(start: &str, end: &str) -> QuietHours {
+        QuietHoursConfig { start: start.to_string(), end: end.to_string(), timezone: None, merch_bypass: true, queue: true }.build()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        format!("2024-01-01T{time}:00Z").parse().unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let quiet_hours = quiet_hours("23:00", "07:00");
+
+        assert!(quiet_hours.is_quiet(at("23:00")));
+        assert!(quiet_hours.is_quiet(at("03:00")));
+        assert!(!quiet_hours.is_quiet(at("07:00")));
+        assert!(!quiet_hours.is_quiet(at("12:00")));
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet_hours = quiet_hours("09:00", "17:00");
+
+        assert!(quiet_hours.is_quiet(at("09:00")));
+        assert!(!quiet_hours.is_quiet(at("17:00")));
+        assert!(!quiet_hours.is_quiet(at("23:00")));
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_max() {
+        let retry = RetryConfig { attempts: 10, delay: 30, max_delay: 200 };
+
+        assert_eq!(retry.delay_after(1), Duration::from_secs(30));
+        assert_eq!(retry.delay_after(2), Duration::from_secs(60));
+        assert_eq!(retry.delay_after(3), Duration::from_secs(120));
+        assert_eq!(retry.delay_after(4), Duration::from_secs(200));
+        assert_eq!(retry.delay_after(100), Duration::from_secs(200));
+    }
+}
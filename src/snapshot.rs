@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{CONFIG_NAMES, SITES_DIR};
+
+const STATE_ENTRY: &str = "state";
+
+/// bundles the config & state directory into a single tar to move or back up a setup
+pub fn export(path: &Path, config_path: &Path, state_dir: &Path) -> anyhow::Result<()> {
+    let mut archive = tar::Builder::new(File::create(path)?);
+
+    // kept under the default name of its format, whatever --config called it
+    let config_entry = config_name(config_path).context("config has no known extension")?;
+    archive.append_path_with_name(config_path, config_entry)
+        .context("failed to add config to snapshot")?;
+
+    let sites_dir = config_path.with_file_name(SITES_DIR);
+    if sites_dir.is_dir() {
+        archive.append_dir_all(SITES_DIR, sites_dir)
+            .context("failed to add sites.d to snapshot")?;
+    }
+
+    if state_dir.is_dir() {
+        archive.append_dir_all(STATE_ENTRY, state_dir)
+            .context("failed to add state directory to snapshot")?;
+    }
+
+    archive.finish()?;
+    Ok(())
+}
+
+/// restores the config (and sites.d) from a snapshot to the config path, or the working directory if there's none.
+/// has to run before the config is loaded
+pub fn import_config(path: &Path, config_path: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut restored = None;
+    let sites_dir = config_path.unwrap_or(Path::new(CONFIG_NAMES[0])).with_file_name(SITES_DIR);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+
+        // older snapshots kept the config under its own name, so any top level config file counts
+        let top_level = entry_path.components().count() == 1;
+        if let Some(name) = config_name(&entry_path).filter(|_| top_level) {
+            let target = config_path.map_or_else(|| PathBuf::from(name), Path::to_path_buf);
+            entry.unpack(&target)?;
+            restored = Some(target);
+        } else if let Ok(relative) = entry_path.strip_prefix(SITES_DIR) {
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+
+            if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+                anyhow::bail!("snapshot entry {} escapes sites.d", entry_path.display());
+            }
+
+            std::fs::create_dir_all(&sites_dir)?;
+            entry.unpack(sites_dir.join(relative))?;
+        }
+    }
+
+    restored.context("snapshot has no config")
+}
+
+/// the default config name with the same format as the path
+fn config_name(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?;
+    CONFIG_NAMES.into_iter().find(|name| Path::new(name).extension() == Some(extension))
+}
+
+/// restores the state directory from a snapshot into the state directory of the (restored) config
+pub fn import_state(path: &Path, state_dir: &Path) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    std::fs::create_dir_all(state_dir)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+
+        if let Ok(relative) = entry_path.strip_prefix(STATE_ENTRY) {
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+
+            if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+                anyhow::bail!("snapshot entry {} escapes the state directory", entry_path.display());
+            }
+
+            let target = state_dir.join(relative);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            entry.unpack(&target)?;
+        }
+    }
+
+    Ok(())
+}
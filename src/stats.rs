@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::website_data::CooldownState;
+
+/// lifetime stats of a site, stored in the state directory so they survive restarts
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct SiteStats {
+    pub total_checks: u64,
+    pub total_notifications: u64,
+    pub total_errors: u64,
+    score_sum: f64,
+    score_count: u64,
+    /// unix timestamp (secs) of the last detected change
+    last_change: Option<u64>,
+    /// lowest score the warmup captures got against each other, 1 means the site rendered identically
+    pub baseline_stability: Option<f64>,
+    /// cooldown counters as of the last save, only used for --explain
+    pub cooldown: CooldownState,
+    /// hashes of the feed items seen, least recently seen first, none until the feed was seeded
+    pub seen_items: Option<VecDeque<u64>>,
+}
+
+/// turns a url into something that can be used as a file name in the state directory
+pub fn state_key(url: &str) -> String {
+    url.trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn unix_now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+impl SiteStats {
+    pub fn path(state_dir: &Path, url: &str) -> PathBuf {
+        state_dir.join(format!("{}.stats.json", state_key(url)))
+    }
+
+    pub fn load(path: &Path) -> SiteStats {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return SiteStats::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Corrupt stats file {} -> {e:?}", path.display());
+            SiteStats::default()
+        })
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+
+    pub fn record_score(&mut self, score: f64) {
+        self.score_sum += score;
+        self.score_count += 1;
+    }
+
+    pub fn record_change(&mut self) {
+        self.last_change = Some(unix_now());
+    }
+
+    pub fn average_score(&self) -> Option<f64> {
+        if self.score_count == 0 {
+            return None;
+        }
+
+        Some(self.score_sum / self.score_count as f64)
+    }
+}
+
+impl Display for SiteStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "checks={},notifications={},errors={}", self.total_checks, self.total_notifications, self.total_errors)?;
+
+        match self.average_score() {
+            Some(average) => write!(f, ",avg score={average:.4}")?,
+            None => write!(f, ",avg score=none")?,
+        }
+
+        if let Some(stability) = self.baseline_stability {
+            write!(f, ",baseline stability={stability:.4}")?;
+        }
+
+        match self.last_change {
+            Some(last_change) => write!(f, ",last change={}s ago", unix_now().saturating_sub(last_change)),
+            None => write!(f, ",last change=never"),
+        }
+    }
+}
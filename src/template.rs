@@ -0,0 +1,53 @@
+/// placeholders every template can use
+pub fn alert_values(url: &str, score: Option<f64>, merch: bool) -> Vec<(&'static str, String)> {
+    vec![
+        ("url", url.to_string()),
+        ("score", score.map(|score| score.to_string()).unwrap_or_default()),
+        // how much it changed, the opposite of the similarity score
+        ("score_pct", score.map(|score| format!("{:.1}", (1.0 - score) * 100.0)).unwrap_or_default()),
+        ("merch_flag", if merch { String::from("MERCH DETECTED!") } else { String::new() }),
+    ]
+}
+
+/// fills in the {name} placeholders of a notification template, unknown ones are left as they are
+pub fn render(template: &str, values: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        let name = &rest[1..end];
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_known_placeholders() {
+        let values = alert_values("https://example.com", Some(0.25), true);
+        assert_eq!(render("{merch_flag} {url} changed {score_pct}%", &values), "MERCH DETECTED! https://example.com changed 75.0%");
+    }
+
+    #[test]
+    fn leaves_unknown_and_unclosed_placeholders() {
+        let values = alert_values("https://example.com", None, false);
+        assert_eq!(render("{nope} {score}|{url", &values), "{nope} |{url");
+    }
+}
@@ -0,0 +1,117 @@
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use oxc_allocator::Allocator;
+use oxc_parser::Parser;
+use oxc_span::SourceType;
+use scraper::Selector;
+use url::Url;
+
+use crate::notify::Fanout;
+use crate::SitesConfig;
+
+/// everything wrong with the config, without launching the browser or sending anything
+pub fn validate(mut config: SitesConfig) -> Vec<String> {
+    // the config is validated with panics, their messages are the problems so they shouldn't be printed as crashes too
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut problems = vec![];
+
+    if config.sites.is_empty() {
+        problems.push(String::from("no sites added"));
+    }
+
+    if !(0.0..1.0).contains(&config.cycle_jitter) {
+        problems.push(String::from("cycle jitter has to be >= 0 & < 1"));
+    }
+
+    if config.coordinated_change_fraction.is_some_and(|fraction| fraction <= 0.0 || fraction > 1.0) {
+        problems.push(String::from("coordinated change fraction has to be > 0 & <= 1"));
+    }
+
+    if config.fail_fast_after == Some(0) {
+        problems.push(String::from("fail fast after has to be > 0"));
+    }
+
+    if let Some(quiet_hours) = config.quiet_hours.take() {
+        if let Err(e) = catch_invalid(|| quiet_hours.build()) {
+            problems.push(format!("quiet hours: {e}"));
+        }
+    }
+
+    // only the configs get checked, building the notifiers would already connect some of them
+    let notifiers = Fanout::with_default(std::mem::take(&mut config.notifiers));
+    for (name, notifier) in &notifiers {
+        if let Err(e) = catch_invalid(|| notifier.validate()) {
+            problems.push(format!("notifier {name}: {e}"));
+        }
+    }
+
+    if let Some(rate_limit) = config.rate_limit {
+        if let Err(e) = catch_invalid(|| rate_limit.validate()) {
+            problems.push(e);
+        }
+    }
+
+    let names = notifiers.keys().map(String::as_str).collect::<Vec<&str>>();
+
+    for site in std::mem::take(&mut config.sites) {
+        let url = site.url().to_string();
+
+        let site = match catch_invalid(|| site.build(&config.state_dir, &config.merch_keywords)) {
+            Ok(site) => site,
+            Err(e) => {
+                problems.push(format!("{url}: {e}"));
+                continue;
+            }
+        };
+
+        if let Err(e) = catch_invalid(|| crate::validate_sites(std::slice::from_ref(&site), &names)) {
+            problems.push(e);
+        }
+
+        problems.extend(site.problems().into_iter().map(|problem| format!("{} {problem}", site.url())));
+    }
+
+    std::panic::set_hook(hook);
+    problems
+}
+
+// runs something that validates with panics, the panic message is the error
+fn catch_invalid<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(payload.as_ref()))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => payload.downcast_ref::<&str>().map_or_else(|| String::from("invalid"), |message| message.to_string()),
+    }
+}
+
+pub fn url_error(url: &str) -> Option<String> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https" | "file") => None,
+        Ok(parsed) => Some(format!("url {url} has unsupported scheme {}", parsed.scheme())),
+        Err(e) => Some(format!("url {url} is invalid -> {e}")),
+    }
+}
+
+/// only css the page can query, checked the way a browser parses it rather than by chromium itself
+pub fn selector_error(selector: &str) -> Option<String> {
+    Selector::parse(selector)
+        .err()
+        .map(|e| format!("selector {selector} is invalid -> {e}"))
+}
+
+pub fn script_error(script: &str) -> Option<String> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, script, SourceType::script()).parse();
+
+    let errors = parsed.diagnostics.errors()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<String>>();
+
+    (!errors.is_empty()).then(|| format!("script {script} doesn't parse -> {}", errors.join(", ")))
+}
@@ -1,29 +1,400 @@
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use image::RgbImage;
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use image::{GrayImage, RgbImage};
+use rand::Rng;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::compare::{CompareOptions, MaskLearner};
+use crate::stats::{SiteStats, state_key, unix_now};
+use crate::validate;
 
 #[derive(Deserialize, Debug)]
 pub struct WebsiteDataConfig {
+    #[serde(default)]
     url: String,
+    /// several pages (like the pages of a listing) watched as one site, instead of url
+    #[serde(default)]
+    urls: Vec<String>,
+    /// false to skip the site without removing it, it keeps its state for when it's turned back on
+    #[serde(default = "WebsiteDataConfig::default_enabled")]
+    enabled: bool,
 
-    /// add a js script to run when the site loads
+    /// add a js script to run when the site loads, either a string or { code, when } to only run it sometimes
     #[serde(rename = "scripts")]
-    scripts: Option<Vec<String>>,
+    scripts: Option<Vec<ScriptConfig>>,
+    /// js that returns a value, when it's truthy an alert is forced no matter the score (or with decision = "gate" required for one)
+    decision_script: Option<String>,
+    /// force or gate, what a truthy decision script does
+    #[serde(default)]
+    decision: Decision,
+    /// follow this iframe and capture & evaluate inside of it instead of the outer page
+    #[serde(rename = "iframe")]
+    iframe_selector: Option<String>,
+    /// only capture when the text of this selector changed since the last run, "body" for the whole page
+    text_prefilter: Option<String>,
+    /// regexes stripped from the text before comparing it (text pre filter & feed items), to ignore clocks & counters
+    #[serde(default)]
+    ignore_text_patterns: Vec<String>,
     /// capture a specific element instead of the whole page (don't use elements that overflow page)
     selector: Option<String>,
+    /// compare the pixels of this canvas (read with toDataURL) instead of a screenshot, for charts & maps
+    canvas_selector: Option<String>,
     /// automatically remove elements when the page loads
     #[serde(rename = "remove")]
     remove_elements: Option<Vec<String>>,
+    /// wait for this page lifecycle event before the wait starts, instead of just after navigating
+    capture_after: Option<CaptureAfter>,
+    /// render at this device pixel ratio, higher catches smaller changes but uses more memory
+    #[serde(alias = "device_scale_factor")]
+    device_pixel_ratio: Option<f64>,
+    /// width & height of the viewport in css pixels, the browser window's if not set
+    viewport_width: Option<u32>,
+    viewport_height: Option<u32>,
+    /// emulate a mobile device (mobile layout, touch)
+    mobile: Option<bool>,
+    /// device to emulate, see DEVICE_PRESETS, the other viewport options override its values
+    device: Option<String>,
+    /// what to do with alert/confirm/prompt/beforeunload dialogs so they don't hang the capture
+    #[serde(default)]
+    dialog_action: DialogAction,
     /// wait x ms before screenshotting to allow dynamic page to load
     #[serde(default)]
     wait: u64,
+    /// seconds between two checks of this site, every cycle if not set
+    interval: Option<u64>,
+    /// cron expression of when to check this site instead of an interval, like "*/5 9-18 * * MON-FRI"
+    schedule: Option<String>,
+    /// timezone the schedule is in, utc if not set
+    schedule_timezone: Option<String>,
+    /// fraction the interval randomly varies by (0.2 is ±20%), or of the time until the next schedule match it's delayed by
+    #[serde(default)]
+    jitter: f64,
+    /// keep scrolling down before capturing until this selector shows up, for infinite scroll pages
+    scroll_until_selector: Option<String>,
+    /// give up scrolling for the selector after this many screens
+    #[serde(default = "WebsiteDataConfig::default_max_scrolls")]
+    max_scrolls: u32,
+    /// disable the browser cache so every capture reflects the live server response
+    #[serde(default)]
+    disable_cache: bool,
+    /// user agent to load this site with instead of the default one
+    user_agent: Option<String>,
+    /// set before every navigation, for consent or region cookies the page needs to render the same every time
+    #[serde(default)]
+    cookies: Vec<CookieConfig>,
+    /// extra headers sent with every request while this site is loaded
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// minimum ms between two navigations to this site's origin, overrides the global one
+    min_navigation_interval: Option<u64>,
+    /// load the site even if its tls certificate is invalid or self signed, overrides the global one
+    ignore_cert_errors: Option<bool>,
+    /// alert when the site's tls certificate expires within this many days, read off of the normal navigation
+    cert_expiry_days: Option<u64>,
     /// when to notify of the change of the site from 0-1, with 0 being totally different, and 1 being the exact same
     #[serde(default = "WebsiteDataConfig::default_threshold")]
     threshold: f64,
+    /// compare an exponential moving average of the score against the threshold instead of the raw score, 0-1 with 1 being no smoothing
+    score_smoothing: Option<f64>,
+    /// pick the notification priority from how low the score went, the lowest matching `below` wins
+    #[serde(default)]
+    priority_thresholds: Vec<PriorityThreshold>,
+    /// priority of this site's alerts when no priority threshold matches
+    #[serde(default)]
+    priority: i8,
+    /// pushover sound of this site's notifications, the user's default if not set
+    sound: Option<String>,
+    /// after a change, the score has to rise above this before the site can alert again (hysteresis), has to be >= threshold
+    clear_threshold: Option<f64>,
+    /// the score has to be at least this much lower than the score of the last alert to alert again, stops slow drift re-alerting
+    #[serde(default)]
+    min_score_delta: f64,
+    /// don't alert when the capture is identical to one of the last this many alerted ones, stops sites flapping between two states
+    #[serde(default)]
+    dedup_states: usize,
+    /// runs at the start that never alert, their scores are used to check the site is stable enough to watch
+    #[serde(default = "WebsiteDataConfig::default_warmup_runs")]
+    warmup_runs: u64,
     /// after noticing a change, how many times should refresh & verify that the site actually changed
     #[serde(default = "WebsiteDataConfig::default_confirmations")]
     confirmations: u32,
+    /// false to alert on the first sample without confirming, faster but more false alarms
+    #[serde(default = "WebsiteDataConfig::default_confirm")]
+    confirm: bool,
+    /// confirm with a hard reload that bypasses the cache instead of navigating again
+    #[serde(default)]
+    confirm_hard_reload: bool,
+    /// after a confirmed change, wait this many ms & capture once more, if it settled back there's no alert
+    final_verify_delay: Option<u64>,
+    /// black & white png the size of the capture, black pixels are ignored when comparing
+    mask: Option<PathBuf>,
+    /// learn a mask of the areas that keep changing (carousels, videos) over this many runs, saved in the state directory
+    auto_mask: Option<u32>,
+    /// known good captures, only alert when the page matches none of them (a/b variants), instead of comparing against the last capture
+    #[serde(default)]
+    reference_images: Vec<PathBuf>,
+    /// blur both captures by this sigma before comparing to smooth out anti-aliasing noise, 0 to disable
+    #[serde(default)]
+    blur: f32,
+    /// when the capture size changes, compare only the overlapping top region instead of treating it as totally different
+    #[serde(default)]
+    crop_to_common: bool,
+    /// compare luminance only so color rendering differences don't count, overrides the global one
+    grayscale_compare: Option<bool>,
+    /// composite transparent parts of the capture onto this rgb color so transparency doesn't count as a change
+    transparent_as: Option<[u8; 3]>,
+    /// feed mode, alert when new items matching this selector show up instead of comparing screenshots
+    #[serde(rename = "feed")]
+    feed_selector: Option<String>,
+    /// presence mode, alert when the selector flips to the expected state instead of comparing screenshots
+    watch_presence: Option<PresenceConfig>,
+    /// compare mode, alert when url & this url look different from each other (staging vs production) instead of from before
+    compare_with: Option<String>,
+    /// send a heads up after this many cycles in a row without a change, in case the site broke in a way that looks stable
+    warn_if_unchanged_for: Option<u64>,
+    /// link notifications here instead of the monitored url, like the product page of an api endpoint
+    notify_url: Option<String>,
+    /// title shown for the notification link instead of the url
+    notify_url_title: Option<String>,
+    /// names of the notifiers to alert through, every one if empty
+    #[serde(default)]
+    notifiers: Vec<String>,
+    /// merch keywords of this site instead of the global ones
+    merch_keywords: Option<Vec<String>>,
+    /// merch keywords on top of the global ones (or the site's own)
+    #[serde(default)]
+    extra_merch_keywords: Vec<String>,
+    /// title of the notifications, can use {url}, {score}, {score_pct} & {merch_flag}
+    #[serde(default = "WebsiteDataConfig::default_title_template")]
+    title_template: String,
+    /// body of the change notifications, can use the title placeholders & {pages}
+    #[serde(default = "WebsiteDataConfig::default_message_template")]
+    message_template: String,
+    /// how stacked cooldowns recover on a quiet cycle, linear takes off cooldown_decay_step, reset clears them, none keeps them
+    #[serde(default)]
+    cooldown_decay: CooldownDecay,
+    /// how many stacked cooldowns linear decay takes off per quiet cycle
+    #[serde(default = "WebsiteDataConfig::default_cooldown_decay_step")]
+    cooldown_decay_step: u32,
+    /// alert once when any of these show up in the page's text, no matter if the page visibly changed
+    #[serde(default)]
+    alert_if_contains: Vec<String>,
+    /// alert once when any of these disappear from the page's text
+    #[serde(default)]
+    alert_if_not_contains: Vec<String>,
+    /// minimum runs between two text alerts
+    #[serde(default)]
+    text_alert_cooldown: u64,
+    /// save the page's html to the state directory every time a change is detected
+    #[serde(default)]
+    save_html: bool,
+    /// add a rough diff against the last saved html to the notification, needs save_html
+    #[serde(default)]
+    html_diff: bool,
+    /// also capture these elements in the same pass, each compared with its own threshold
+    #[serde(default)]
+    regions: Vec<RegionConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegionConfig {
+    selector: String,
+    #[serde(default = "WebsiteDataConfig::default_threshold")]
+    threshold: f64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ScriptConfig {
+    Always(String),
+    Conditional {
+        code: String,
+        /// "always", "first_run", "confirmation" or "if_selector:<selector>"
+        when: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct Script {
+    code: String,
+    when: ScriptCondition,
+}
+
+#[derive(Debug)]
+pub enum ScriptCondition {
+    Always,
+    /// only on the first run since starting
+    FirstRun,
+    /// only while confirming a change
+    Confirmation,
+    /// only if the selector is on the page
+    IfSelector(String),
+}
+
+impl Script {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn when(&self) -> &ScriptCondition {
+        &self.when
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureAfter {
+    DomContentLoaded,
+    Load,
+    NetworkIdle,
+}
+
+impl CaptureAfter {
+    /// name of the cdp lifecycle event
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            CaptureAfter::DomContentLoaded => "DOMContentLoaded",
+            CaptureAfter::Load => "load",
+            CaptureAfter::NetworkIdle => "networkIdle",
+        }
+    }
+}
+
+/// screen a site is rendered on, 0 keeps the browser's own value
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DeviceMetrics {
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    pub mobile: bool,
+}
+
+/// devices that can be emulated by name, with their metrics & user agent
+const DEVICE_PRESETS: [(&str, DeviceMetrics, &str); 6] = [
+    (
+        "iPhone 13",
+        DeviceMetrics { width: 390, height: 844, scale: 3.0, mobile: true },
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    ),
+    (
+        "iPhone SE",
+        DeviceMetrics { width: 375, height: 667, scale: 2.0, mobile: true },
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    ),
+    (
+        "iPhone 14 Pro Max",
+        DeviceMetrics { width: 430, height: 932, scale: 3.0, mobile: true },
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1",
+    ),
+    (
+        "Pixel 7",
+        DeviceMetrics { width: 412, height: 915, scale: 2.625, mobile: true },
+        "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36",
+    ),
+    (
+        "Galaxy S20",
+        DeviceMetrics { width: 360, height: 800, scale: 3.0, mobile: true },
+        "Mozilla/5.0 (Linux; Android 13; SM-G981B) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36",
+    ),
+    (
+        "iPad Air",
+        DeviceMetrics { width: 820, height: 1180, scale: 2.0, mobile: true },
+        "Mozilla/5.0 (iPad; CPU OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    ),
+];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// truthy alerts even if the page didn't visibly change
+    #[default]
+    Force,
+    /// the page has to visibly change and the script has to be truthy
+    Gate,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DialogAction {
+    #[default]
+    Dismiss,
+    Accept,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct PriorityThreshold {
+    below: f64,
+    priority: i8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CookieConfig {
+    name: String,
+    value: String,
+    /// the site's own url if not set
+    domain: Option<String>,
+    path: Option<String>,
+}
+
+impl CookieConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn domain(&self) -> &Option<String> {
+        &self.domain
+    }
+
+    pub fn path(&self) -> &Option<String> {
+        &self.path
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PresenceConfig {
+    selector: String,
+    expect: Presence,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    Present,
+    Absent,
+}
+
+impl PresenceConfig {
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn expects_present(&self) -> bool {
+        self.expect == Presence::Present
+    }
+}
+
+impl RegionConfig {
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
 }
 
 impl WebsiteDataConfig {
@@ -34,24 +405,85 @@ impl WebsiteDataConfig {
     fn default_confirmations() -> u32 {
         3
     }
+
+    fn default_warmup_runs() -> u64 {
+        3
+    }
+
+    fn default_confirm() -> bool {
+        true
+    }
+
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_title_template() -> String {
+        String::from("Website Change Detected")
+    }
+
+    fn default_message_template() -> String {
+        String::from("Found changes on {pages} with an average difference rating of {score}.{merch_flag}")
+    }
+
+    fn default_cooldown_decay_step() -> u32 {
+        1
+    }
+
+    fn default_max_scrolls() -> u32 {
+        20
+    }
 }
 
 impl WebsiteDataConfig {
+    /// the url the site will be identified by, before it's built
+    pub fn url(&self) -> &str {
+        self.urls.first().unwrap_or(&self.url)
+    }
+
     fn format_script(script: String) -> String {
         format!("()=>{{{}}}", script)
     }
 
+    fn parse_script_condition(when: &str) -> ScriptCondition {
+        match when {
+            "always" => ScriptCondition::Always,
+            "first_run" => ScriptCondition::FirstRun,
+            "confirmation" => ScriptCondition::Confirmation,
+            _ => match when.strip_prefix("if_selector:") {
+                Some(selector) if !selector.is_empty() => ScriptCondition::IfSelector(selector.to_string()),
+                _ => panic!("unknown script condition {when}"),
+            },
+        }
+    }
+
     fn format_remove_elements(elements: Vec<String>) -> String {
        WebsiteDataConfig::format_script(
            format!("document.querySelectorAll('{}')?.forEach(a => a?.remove());", elements.join(", "))
        )
     }
 
-    pub fn build(mut self) -> WebsiteData {
-        if self.url.is_empty() {
+    pub fn build(mut self, state_dir: &Path, merch_keywords: &[String]) -> WebsiteData {
+        if !self.urls.is_empty() {
+            if !self.url.is_empty() {
+                panic!("url & urls can't be used together");
+            }
+
+            self.url = self.urls[0].clone();
+        }
+
+        if self.url.is_empty() || self.urls.iter().any(|url| url.is_empty()) {
             panic!("require url to be non blank");
         }
 
+        if self.urls.len() > 1 && (self.feed_selector.is_some() || self.watch_presence.is_some() || self.compare_with.is_some()) {
+            panic!("urls can't be used with feed, watch_presence or compare_with");
+        }
+
+        if self.compare_with.is_some() && (self.feed_selector.is_some() || self.watch_presence.is_some()) {
+            panic!("compare_with can't be used with feed or watch_presence");
+        }
+
         if self.confirmations == 0 {
             panic!("max confirms has to be >0");
         }
@@ -60,47 +492,464 @@ impl WebsiteDataConfig {
             panic!("threshold has to be > 0 & < 1")
         }
 
+        if self.device_pixel_ratio.is_some_and(|dpr| dpr <= 0.0) {
+            panic!("device pixel ratio has to be > 0")
+        }
+
+        if self.blur < 0.0 {
+            panic!("blur has to be >= 0")
+        }
+
+        if self.interval == Some(0) {
+            panic!("interval has to be > 0")
+        }
+
+        if !(0.0..1.0).contains(&self.jitter) {
+            panic!("jitter has to be >= 0 & < 1")
+        }
+
+        if self.interval.is_some() && self.schedule.is_some() {
+            panic!("interval & schedule can't be used together")
+        }
+
+        let schedule = self.schedule.as_deref().map(|schedule| schedule.parse::<Cron>()
+            .unwrap_or_else(|e| panic!("invalid schedule {schedule} -> {e}")));
+        let schedule_timezone = self.schedule_timezone.as_deref()
+            .map(|timezone| timezone.parse().unwrap_or_else(|_| panic!("unknown schedule timezone {timezone}")))
+            .unwrap_or(Tz::UTC);
+
+        // 2 is pushover's emergency priority, which needs a retry & expire that only merch detections get
+        if !(-2..=1).contains(&self.priority) {
+            panic!("priority has to be between -2 & 1")
+        }
+
+        for priority_threshold in &self.priority_thresholds {
+            if priority_threshold.below < 0.0 || priority_threshold.below > 1.0 {
+                panic!("priority threshold has to be > 0 & < 1")
+            }
+
+            if !(-2..=1).contains(&priority_threshold.priority) {
+                panic!("priority threshold priority has to be between -2 & 1")
+            }
+        }
+
+        if let Some(score_smoothing) = self.score_smoothing {
+            if score_smoothing <= 0.0 || score_smoothing > 1.0 {
+                panic!("score smoothing has to be > 0 & <= 1")
+            }
+        }
+
+        if let Some(clear_threshold) = self.clear_threshold {
+            if clear_threshold < self.threshold || clear_threshold > 1.0 {
+                panic!("clear threshold has to be >= threshold & < 1")
+            }
+        }
+
+        if self.min_score_delta < 0.0 || self.min_score_delta >= 1.0 {
+            panic!("min score delta has to be >= 0 & < 1")
+        }
+
+        for region in &self.regions {
+            if region.selector.is_empty() {
+                panic!("region selector has to be non blank");
+            }
+
+            if region.threshold < 0.0 || region.threshold > 1.0 {
+                panic!("region threshold has to be > 0 & < 1")
+            }
+        }
+
+        if self.canvas_selector.is_some() && self.selector.is_some() {
+            panic!("selector & canvas_selector can't be used together");
+        }
+
+        if self.feed_selector.is_some() && self.watch_presence.is_some() {
+            panic!("feed & watch_presence can't be used together");
+        }
+
         let mut scripts = vec![];
 
         if let Some(elements) = self.remove_elements.take() {
-            scripts.push(WebsiteDataConfig::format_remove_elements(elements));
+            scripts.push(Script {
+                code: WebsiteDataConfig::format_remove_elements(elements),
+                when: ScriptCondition::Always,
+            });
         }
 
         if let Some(new_scripts) = self.scripts.take() {
             for script in new_scripts {
-                scripts.push(WebsiteDataConfig::format_script(script));
+                let (code, when) = match script {
+                    ScriptConfig::Always(code) => (code, ScriptCondition::Always),
+                    ScriptConfig::Conditional { code, when } => (code, WebsiteDataConfig::parse_script_condition(&when)),
+                };
+
+                scripts.push(Script {
+                    code: WebsiteDataConfig::format_script(code),
+                    when,
+                });
             }
         }
 
+        if self.auto_mask.is_some() && (self.mask.is_some() || self.urls.len() > 1) {
+            panic!("auto_mask can't be used with mask or urls");
+        }
+
+        if self.auto_mask == Some(0) {
+            panic!("auto mask has to learn over >0 runs");
+        }
+
+        // a mask learned on an earlier run is used like a configured one
+        let mask_path = state_dir.join("masks").join(format!("{}.png", state_key(&self.url)));
+        let learned_mask = self.auto_mask.is_some() && mask_path.exists();
+        if learned_mask {
+            self.mask = Some(mask_path.clone());
+        }
+
+        let mask = self.mask.take().map(|path| match image::open(&path) {
+            Ok(mask) => mask.into_luma8(),
+            Err(e) => panic!("failed to load mask {} -> {e:?}", path.display()),
+        });
+
+        let reference_images = self.reference_images.iter()
+            .map(|path| match image::open(path) {
+                Ok(reference) => reference.into_rgb8(),
+                Err(e) => panic!("failed to load reference image {} -> {e:?}", path.display()),
+            })
+            .collect();
+
+        let ignore_text_patterns = self.ignore_text_patterns.iter()
+            .map(|pattern| match Regex::new(pattern) {
+                Ok(pattern) => pattern,
+                Err(e) => panic!("invalid ignore text pattern {pattern} -> {e}"),
+            })
+            .collect();
+
+        let mut merch_keywords = self.merch_keywords.take().unwrap_or_else(|| merch_keywords.to_vec());
+        merch_keywords.append(&mut self.extra_merch_keywords);
+
+        let preset = self.device.as_deref().map(|device| match DEVICE_PRESETS.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(device)) {
+            Some((_, metrics, user_agent)) => (*metrics, *user_agent),
+            None => panic!("unknown device {device}, pick one of {:?}", DEVICE_PRESETS.map(|(name, _, _)| name)),
+        });
+
+        let emulated = preset.is_some() || self.viewport_width.is_some() || self.viewport_height.is_some() || self.device_pixel_ratio.is_some() || self.mobile.is_some();
+        let device_metrics = emulated.then(|| {
+            let preset = preset.map(|(metrics, _)| metrics).unwrap_or_default();
+
+            DeviceMetrics {
+                width: self.viewport_width.unwrap_or(preset.width),
+                height: self.viewport_height.unwrap_or(preset.height),
+                scale: self.device_pixel_ratio.unwrap_or(preset.scale),
+                mobile: self.mobile.unwrap_or(preset.mobile),
+            }
+        });
+
+        let stats_path = SiteStats::path(state_dir, &self.url);
+        let html_dir = state_dir.join("html").join(state_key(&self.url));
+
+        // the compared url is checked as a second page of the site
+        let compare_mode = self.compare_with.is_some();
+        let urls = match self.compare_with.take() {
+            Some(compare_with) => vec![self.url.clone(), compare_with],
+            None if self.urls.is_empty() => vec![self.url.clone()],
+            None => self.urls,
+        };
+        let pages = urls.iter()
+            .map(|_| PageBaseline { region_images: vec![None; self.regions.len()], ..PageBaseline::default() })
+            .collect();
+
         WebsiteData {
+            stats: SiteStats::load(&stats_path),
+            stats_path,
             url: self.url,
+            urls,
+            enabled: self.enabled,
+            current_page: 0,
+            pages,
             scripts,
+            decision_script: self.decision_script.map(WebsiteDataConfig::format_script),
+            decision: self.decision,
+            iframe_selector: self.iframe_selector,
+            text_prefilter: self.text_prefilter,
+            ignore_text_patterns,
             screenshot_selector: self.selector,
+            canvas_selector: self.canvas_selector,
+            capture_after: self.capture_after,
+            device_metrics,
+            dialog_action: self.dialog_action,
             wait: self.wait,
+            interval: self.interval,
+            next_check: None,
+            jitter: self.jitter,
+            next_run: schedule.as_ref().and_then(|schedule| next_occurrence(schedule, schedule_timezone, true)),
+            schedule,
+            schedule_timezone,
+            scroll_until_selector: self.scroll_until_selector,
+            max_scrolls: self.max_scrolls,
+            disable_cache: self.disable_cache,
+            cookies: self.cookies,
+            headers: self.headers,
+            user_agent: self.user_agent.or(preset.map(|(_, user_agent)| user_agent.to_string())),
+            min_navigation_interval: self.min_navigation_interval,
+            ignore_cert_errors: self.ignore_cert_errors,
+            cert_expiry_days: self.cert_expiry_days,
             threshold: self.threshold,
+            clear_threshold: self.clear_threshold,
+            min_score_delta: self.min_score_delta,
+            dedup_states: self.dedup_states,
+            alerted_hashes: VecDeque::new(),
+            last_alert_score: None,
+            priority_thresholds: self.priority_thresholds,
+            priority: self.priority,
+            sound: self.sound,
+            score_smoothing: self.score_smoothing,
+            score_ema: None,
             max_confirms: self.confirmations,
+            confirm: self.confirm,
+            confirm_hard_reload: self.confirm_hard_reload,
+            final_verify_delay: self.final_verify_delay,
+            mask,
+            mask_learner: (self.auto_mask.is_some() && !learned_mask).then(MaskLearner::new),
+            auto_mask_runs: self.auto_mask.unwrap_or_default(),
+            mask_path,
+            reference_images,
+            blur: self.blur,
+            crop_to_common: self.crop_to_common,
+            grayscale_compare: self.grayscale_compare,
+            transparent_as: self.transparent_as,
+            feed_selector: self.feed_selector,
+            watch_presence: self.watch_presence,
+            compare_mode,
+            warn_if_unchanged_for: self.warn_if_unchanged_for,
+            cooldown_decay: self.cooldown_decay,
+            cooldown_decay_step: self.cooldown_decay_step,
+            notify_url: self.notify_url,
+            notify_url_title: self.notify_url_title,
+            notifiers: self.notifiers,
+            merch_keywords,
+            title_template: self.title_template,
+            message_template: self.message_template,
+            alert_if_contains: self.alert_if_contains,
+            alert_if_not_contains: self.alert_if_not_contains,
+            text_alert_cooldown: self.text_alert_cooldown,
+            save_html: self.save_html,
+            html_diff: self.html_diff,
+            html_dir,
+            region_images: vec![None; self.regions.len()],
+            regions: self.regions,
 
             last_image: None,
+            previous_image: None,
+            capture_degraded: false,
             merch_already_detected: false,
+            last_text_hash: None,
+            contains_present: HashMap::new(),
+            not_contains_present: HashMap::new(),
+            last_text_alert: None,
+            last_presence: None,
+            cert_valid_to: None,
+            cert_alerted_for: None,
+            change_latched: false,
             changes_stacking: 0,
             current_cooldown: 0,
             total_cooldowns: 0,
             total_runs: 0,
+            warmup_runs: self.warmup_runs,
+            warmup_scores: vec![],
+            unchanged_cycles: 0,
+            failed_checks: 0,
+        }
+    }
+}
+
+/// next time the schedule matches from now, in the schedule's timezone
+fn next_occurrence(schedule: &Cron, timezone: Tz, inclusive: bool) -> Option<DateTime<Utc>> {
+    schedule.find_next_occurrence(&Utc::now().with_timezone(&timezone), inclusive)
+        .ok()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+/// after this many notifications in a row a cooldown is given
+const COOLDOWN_AFTER_CHANGES: u8 = 4;
+/// a cooldown lasts this to the power of the stacked cooldowns cycles
+const COOLDOWN_BASE: u16 = 3;
+/// feed items remembered, the ones not seen on the page for the longest get forgotten first
+const SEEN_ITEMS_LIMIT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CooldownDecay {
+    #[default]
+    Linear,
+    Reset,
+    None,
+}
+
+/// copy of the cooldown counters, saved with the stats so --explain can show them
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct CooldownState {
+    changes_stacking: u8,
+    current_cooldown: u16,
+    total_cooldowns: u32,
+    #[serde(default)]
+    decay: CooldownDecay,
+    #[serde(default)]
+    decay_step: u32,
+}
+
+impl Display for CooldownState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "notifications in a row: {}/{COOLDOWN_AFTER_CHANGES}, {} more can be sent before a cooldown is given",
+            self.changes_stacking,
+            COOLDOWN_AFTER_CHANGES.saturating_sub(self.changes_stacking + 1)
+        )?;
+
+        let decay = match self.decay {
+            CooldownDecay::Linear => format!("{} taken off per quiet cycle", self.decay_step),
+            CooldownDecay::Reset => "all cleared on a quiet cycle".to_string(),
+            CooldownDecay::None => "they never decay".to_string(),
+        };
+
+        writeln!(
+            f,
+            "stacked cooldowns: {}, the next cooldown lasts {} cycles, {decay}",
+            self.total_cooldowns,
+            COOLDOWN_BASE.saturating_pow(self.total_cooldowns + 1)
+        )?;
+
+        if self.current_cooldown == 0 {
+            write!(f, "current cooldown: none")
+        } else {
+            write!(f, "current cooldown: lifts in {} cycles", self.current_cooldown)
         }
     }
 }
 
+/// baselines of one of the urls, the current page's baselines live on the site itself
+#[derive(Debug, Default)]
+struct PageBaseline {
+    last_image: Option<RgbImage>,
+    previous_image: Option<RgbImage>,
+    capture_degraded: bool,
+    region_images: Vec<Option<RgbImage>>,
+    last_text_hash: Option<u64>,
+    score_ema: Option<f64>,
+}
+
 #[derive(Debug)]
 pub struct WebsiteData {
+    /// the first url, identifies the site
     url: String,
-    scripts: Vec<String>,
+    urls: Vec<String>,
+    enabled: bool,
+    current_page: usize,
+    /// baselines of the other pages, swapped in & out as the pages are checked
+    pages: Vec<PageBaseline>,
+    scripts: Vec<Script>,
+    decision_script: Option<String>,
+    decision: Decision,
+    iframe_selector: Option<String>,
+    text_prefilter: Option<String>,
+    ignore_text_patterns: Vec<Regex>,
     screenshot_selector: Option<String>,
+    canvas_selector: Option<String>,
+    capture_after: Option<CaptureAfter>,
+    device_metrics: Option<DeviceMetrics>,
+    dialog_action: DialogAction,
     wait: u64,
+    interval: Option<u64>,
+    /// when the interval is next due, none until the first check
+    next_check: Option<Instant>,
+    jitter: f64,
+    schedule: Option<Cron>,
+    schedule_timezone: Tz,
+    /// when the schedule is next due, none if it never is again
+    next_run: Option<DateTime<Utc>>,
+    scroll_until_selector: Option<String>,
+    max_scrolls: u32,
+    disable_cache: bool,
+    user_agent: Option<String>,
+    cookies: Vec<CookieConfig>,
+    headers: HashMap<String, String>,
+    min_navigation_interval: Option<u64>,
+    ignore_cert_errors: Option<bool>,
+    cert_expiry_days: Option<u64>,
     threshold: f64,
+    clear_threshold: Option<f64>,
+    min_score_delta: f64,
+    /// score of the last change that was alerted on
+    last_alert_score: Option<f64>,
+    dedup_states: usize,
+    /// hashes of the last alerted captures, newest last
+    alerted_hashes: VecDeque<u64>,
+    priority_thresholds: Vec<PriorityThreshold>,
+    priority: i8,
+    sound: Option<String>,
+    score_smoothing: Option<f64>,
+    /// moving average of the score, only with score smoothing
+    score_ema: Option<f64>,
     max_confirms: u32,
+    confirm: bool,
+    confirm_hard_reload: bool,
+    final_verify_delay: Option<u64>,
+    mask: Option<GrayImage>,
+    /// only while an auto mask is still being learned
+    mask_learner: Option<MaskLearner>,
+    auto_mask_runs: u32,
+    mask_path: PathBuf,
+    reference_images: Vec<RgbImage>,
+    blur: f32,
+    crop_to_common: bool,
+    grayscale_compare: Option<bool>,
+    transparent_as: Option<[u8; 3]>,
+    feed_selector: Option<String>,
+    watch_presence: Option<PresenceConfig>,
+    /// if the second url is the one compare mode compares against
+    compare_mode: bool,
+    warn_if_unchanged_for: Option<u64>,
+    cooldown_decay: CooldownDecay,
+    cooldown_decay_step: u32,
+    notify_url: Option<String>,
+    notify_url_title: Option<String>,
+    notifiers: Vec<String>,
+    merch_keywords: Vec<String>,
+    title_template: String,
+    message_template: String,
+    alert_if_contains: Vec<String>,
+    alert_if_not_contains: Vec<String>,
+    text_alert_cooldown: u64,
+    save_html: bool,
+    html_diff: bool,
+    html_dir: PathBuf,
+    regions: Vec<RegionConfig>,
 
     pub last_image: Option<RgbImage>,
+    /// the baseline before the last capture, only kept when the last check found a change
+    pub previous_image: Option<RgbImage>,
+    /// if the baseline is a viewport capture because full page capturing failed
+    pub capture_degraded: bool,
+    /// baselines of the regions, in the same order as the regions
+    pub region_images: Vec<Option<RgbImage>>,
     pub merch_already_detected: bool,
+    /// hash of the text pre filter's text last run
+    last_text_hash: Option<u64>,
+    /// if each of the alert if contains phrases was present last run
+    contains_present: HashMap<String, bool>,
+    /// if each of the alert if not contains phrases was present last run
+    not_contains_present: HashMap<String, bool>,
+    /// run of the last text alert, for its cooldown
+    last_text_alert: Option<u64>,
+    /// if the watched element was present last run, none until the first presence run
+    pub last_presence: Option<bool>,
+    /// unix timestamp (secs) the certificate seen on the last navigation expires at
+    pub cert_valid_to: Option<u64>,
+    /// expiry of the certificate that was already alerted on, so it's only alerted once
+    cert_alerted_for: Option<u64>,
+
+    /// set once a change is declared, cleared when the score recovers above the clear threshold
+    change_latched: bool,
 
     /// in a row, count the number of times i have been texted, used for cooldown
     changes_stacking: u8,
@@ -110,50 +959,660 @@ pub struct WebsiteData {
     total_cooldowns: u32,
 
     total_runs: u64,
+    warmup_runs: u64,
+    /// scores against a baseline during the warmup, to estimate how stable the site is
+    warmup_scores: Vec<f64>,
+    /// cycles in a row without a change, for the unchanged warning
+    unchanged_cycles: u64,
+    /// checks in a row that errored, for the failure alert
+    failed_checks: u64,
+
+    pub stats: SiteStats,
+    stats_path: PathBuf,
 }
 
 // <editor-fold desc="Website data helper functions">
 impl WebsiteData {
-    pub fn scripts(&self) -> &Vec<String> {
+    pub fn scripts(&self) -> &Vec<Script> {
         &self.scripts
     }
     pub fn url(&self) -> &str {
         &self.url
     }
 
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn decision_script(&self) -> &Option<String> {
+        &self.decision_script
+    }
+
+    pub fn decision(&self) -> Decision {
+        self.decision
+    }
+
+    pub fn urls(&self) -> &Vec<String> {
+        &self.urls
+    }
+
+    /// where notifications link to, the monitored url unless overridden
+    pub fn notify_url(&self) -> &str {
+        self.notify_url.as_deref().unwrap_or(&self.url)
+    }
+
+    pub fn notify_url_title(&self) -> &Option<String> {
+        &self.notify_url_title
+    }
+
+    pub fn notifiers(&self) -> &Vec<String> {
+        &self.notifiers
+    }
+
+    pub fn merch_keywords(&self) -> &Vec<String> {
+        &self.merch_keywords
+    }
+
+    pub fn title_template(&self) -> &str {
+        &self.title_template
+    }
+
+    pub fn message_template(&self) -> &str {
+        &self.message_template
+    }
+
+    /// url of the page currently being checked
+    pub fn page_url(&self) -> &str {
+        &self.urls[self.current_page]
+    }
+
+    pub fn iframe_selector(&self) -> &Option<String> {
+        &self.iframe_selector
+    }
+
+    pub fn text_prefilter(&self) -> &Option<String> {
+        &self.text_prefilter
+    }
+
     pub fn screenshot_selector(&self) -> &Option<String> {
         &self.screenshot_selector
     }
 
+    pub fn canvas_selector(&self) -> &Option<String> {
+        &self.canvas_selector
+    }
+
+    pub fn capture_after(&self) -> Option<CaptureAfter> {
+        self.capture_after
+    }
+
+    pub fn device_metrics(&self) -> Option<DeviceMetrics> {
+        self.device_metrics
+    }
+
+    pub fn dialog_action(&self) -> DialogAction {
+        self.dialog_action
+    }
+
     pub fn wait(&self) -> u64 {
         self.wait
     }
 
+    pub fn scroll_until_selector(&self) -> &Option<String> {
+        &self.scroll_until_selector
+    }
+
+    pub fn max_scrolls(&self) -> u32 {
+        self.max_scrolls
+    }
+
+    pub fn disable_cache(&self) -> bool {
+        self.disable_cache
+    }
+
+    pub fn user_agent(&self) -> &Option<String> {
+        &self.user_agent
+    }
+
+    pub fn cookies(&self) -> &Vec<CookieConfig> {
+        &self.cookies
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    pub fn min_navigation_interval(&self) -> Option<u64> {
+        self.min_navigation_interval
+    }
+
+    pub fn ignore_cert_errors(&self) -> Option<bool> {
+        self.ignore_cert_errors
+    }
+
+    pub fn cert_expiry_days(&self) -> Option<u64> {
+        self.cert_expiry_days
+    }
+
     pub fn threshold(&self) -> f64 {
         self.threshold
     }
 
-    pub fn max_confirms(&self) -> u32 {
-        self.max_confirms
+    // priority of the lowest priority threshold the score is below, none if there aren't any/none match
+    pub fn priority_for(&self, score: f64) -> Option<i8> {
+        self.priority_thresholds.iter()
+            .filter(|t| score < t.below)
+            .min_by(|a, b| a.below.total_cmp(&b.below))
+            .map(|t| t.priority)
+    }
+
+    pub fn priority(&self) -> i8 {
+        self.priority
+    }
+
+    pub fn sound(&self) -> &Option<String> {
+        &self.sound
+    }
+
+    /// how many captures to take at most, the first one plus the confirmations
+    pub fn samples(&self) -> u32 {
+        if self.confirm { self.max_confirms } else { 1 }
+    }
+
+    pub fn confirm_hard_reload(&self) -> bool {
+        self.confirm_hard_reload
+    }
+
+    pub fn final_verify_delay(&self) -> Option<u64> {
+        self.final_verify_delay
+    }
+
+    pub fn reference_images(&self) -> &Vec<RgbImage> {
+        &self.reference_images
+    }
+
+    pub fn grayscale_compare(&self) -> Option<bool> {
+        self.grayscale_compare
+    }
+
+    pub fn compare_options(&self) -> CompareOptions<'_> {
+        CompareOptions {
+            mask: self.mask.as_ref(),
+            blur: self.blur,
+            crop_to_common: self.crop_to_common,
+            transparent_as: self.transparent_as,
+            ..CompareOptions::default()
+        }
+    }
+
+    pub fn feed_selector(&self) -> &Option<String> {
+        &self.feed_selector
+    }
+
+    pub fn watch_presence(&self) -> &Option<PresenceConfig> {
+        &self.watch_presence
+    }
+
+    /// a change was declared & the score hasn't recovered above the clear threshold yet
+    pub fn change_latched(&self) -> bool {
+        self.change_latched
+    }
+
+    pub fn compare_mode(&self) -> bool {
+        self.compare_mode
+    }
+
+    pub fn has_text_alerts(&self) -> bool {
+        !self.alert_if_contains.is_empty() || !self.alert_if_not_contains.is_empty()
+    }
+
+    pub fn save_html(&self) -> bool {
+        self.save_html
+    }
+
+    pub fn html_diff(&self) -> bool {
+        self.html_diff
+    }
+
+    pub fn html_dir(&self) -> PathBuf {
+        if self.urls.len() > 1 {
+            self.html_dir.join(state_key(self.page_url()))
+        } else {
+            self.html_dir.clone()
+        }
+    }
+
+    pub fn regions(&self) -> &Vec<RegionConfig> {
+        &self.regions
     }
 
     pub fn get_runs(&self) -> u64 {
         self.total_runs
     }
+
+    pub fn in_warmup(&self) -> bool {
+        self.total_runs <= self.warmup_runs
+    }
+
+    pub fn stats_path(&self) -> &Path {
+        &self.stats_path
+    }
+
+    /// cycles until the site gets checked again, 0 when it's not on a cooldown
+    pub fn cooldown_left(&self) -> u16 {
+        self.current_cooldown
+    }
+
+    pub fn cooldown_state(&self) -> CooldownState {
+        CooldownState {
+            changes_stacking: self.changes_stacking,
+            current_cooldown: self.current_cooldown,
+            total_cooldowns: self.total_cooldowns,
+            decay: self.cooldown_decay,
+            decay_step: self.cooldown_decay_step,
+        }
+    }
 }
 // </editor-fold>
 
 impl WebsiteData {
     pub fn run(&mut self) {
         self.total_runs += 1;
+        self.stats.total_checks += 1;
+    }
+
+    // hysteresis, once a change is declared the score has to recover above the clear threshold before another change counts
+    pub fn latch_change(&mut self, changed: bool, best_score: f64) -> bool {
+        let Some(clear_threshold) = self.clear_threshold else {
+            return changed;
+        };
+
+        if self.change_latched {
+            if best_score >= clear_threshold {
+                self.change_latched = false;
+                // the site recovered, so the next change is measured on its own again
+                self.last_alert_score = None;
+            }
+
+            return false;
+        }
+
+        self.change_latched = changed;
+        changed
+    }
+
+    // returns the items that weren't seen before, or none if this is the first run and the seen items were just seeded
+    pub fn new_feed_items(&mut self, items: Vec<String>) -> Option<Vec<String>> {
+        let first_run = self.stats.seen_items.is_none();
+        let seen_items = self.stats.seen_items.get_or_insert_with(VecDeque::new);
+
+        let items = items.into_iter().filter(|item| !item.is_empty()).collect::<Vec<String>>();
+        let mut new_items = vec![];
+        for item in &items {
+            let hash = hash_text(&strip_patterns(&self.ignore_text_patterns, item));
+
+            // still on the page, so it moves to the back & gets forgotten last
+            match seen_items.iter().position(|seen| *seen == hash) {
+                Some(i) => {
+                    seen_items.remove(i);
+                }
+                None => new_items.push(item.clone()),
+            }
+
+            seen_items.push_back(hash);
+        }
+
+        // whatever is on the page right now is never forgotten, or it would come back as new next run
+        while seen_items.len() > SEEN_ITEMS_LIMIT.max(items.len()) {
+            seen_items.pop_front();
+        }
+
+        if first_run {
+            return None;
+        }
+
+        Some(new_items)
+    }
+
+    // stores the hash of the text, returns if it was different from last time
+    pub fn text_changed(&mut self, text: &str) -> bool {
+        let hash = hash_text(&strip_patterns(&self.ignore_text_patterns, text));
+        self.last_text_hash.replace(hash) != Some(hash)
+    }
+
+    // returns what text alerts would fire this run, phrases are matched case insensitive & only fire when they first flip
+    pub fn text_alerts(&self, text: &str) -> Vec<String> {
+        let text = text.to_lowercase();
+        let mut fired = vec![];
+
+        for phrase in &self.alert_if_contains {
+            let present = text.contains(&phrase.to_lowercase());
+            if present && !self.contains_present.get(phrase).copied().unwrap_or(false) {
+                fired.push(format!("now contains \"{phrase}\""));
+            }
+        }
+
+        for phrase in &self.alert_if_not_contains {
+            let present = text.contains(&phrase.to_lowercase());
+            if !present && self.not_contains_present.get(phrase).copied().unwrap_or(true) {
+                fired.push(format!("no longer contains \"{phrase}\""));
+            }
+        }
+
+        fired
+    }
+
+    pub fn text_alert_on_cooldown(&self) -> bool {
+        self.last_text_alert.is_some_and(|last_text_alert| self.total_runs - last_text_alert < self.text_alert_cooldown)
+    }
+
+    /// remembers which phrases the text has, only once an alert went out so one held back by the cooldown fires later
+    pub fn record_text(&mut self, text: &str, alerted: bool) {
+        let text = text.to_lowercase();
+
+        for phrase in &self.alert_if_contains {
+            self.contains_present.insert(phrase.clone(), text.contains(&phrase.to_lowercase()));
+        }
+
+        for phrase in &self.alert_if_not_contains {
+            self.not_contains_present.insert(phrase.clone(), text.contains(&phrase.to_lowercase()));
+        }
+
+        if alerted {
+            self.last_text_alert = Some(self.total_runs);
+        }
+    }
+
+    // with min score delta, only alert again if the score went meaningfully lower than last alert
+    pub fn far_enough_from_last_alert(&self, score: f64) -> bool {
+        self.min_score_delta == 0.0 || self.last_alert_score.is_none_or(|last| last - score >= self.min_score_delta)
+    }
+
+    pub fn alerted_at(&mut self, score: f64) {
+        self.last_alert_score = Some(score);
+
+        if let Some(hash) = self.capture_hash() {
+            self.alerted_hashes.push_back(hash);
+            if self.alerted_hashes.len() > self.dedup_states {
+                self.alerted_hashes.pop_front();
+            }
+        }
+    }
+
+    /// if the current capture is identical to one that was recently alerted on
+    pub fn already_alerted(&self) -> bool {
+        self.capture_hash().is_some_and(|hash| self.alerted_hashes.contains(&hash))
+    }
+
+    // none when dedup is off, hashing full page captures isn't free
+    fn capture_hash(&self) -> Option<u64> {
+        if self.dedup_states == 0 {
+            return None;
+        }
+
+        let image = self.last_image.as_ref()?;
+        let mut hasher = DefaultHasher::new();
+        image.dimensions().hash(&mut hasher);
+        image.as_raw().hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    // feeds a run into the auto mask, once it learned enough the mask is saved & used from then on
+    pub fn learn_mask(&mut self, last_image: &RgbImage, image: &RgbImage) {
+        let Some(learner) = self.mask_learner.as_mut() else {
+            return;
+        };
+
+        learner.observe(last_image, image);
+        if learner.samples() < self.auto_mask_runs {
+            return;
+        }
+
+        let mask = learner.mask();
+        let saved = std::fs::create_dir_all(self.mask_path.parent().unwrap_or(Path::new(".")))
+            .map_err(anyhow::Error::from)
+            .and_then(|_| mask.save(&self.mask_path).map_err(anyhow::Error::from));
+
+        if let Err(e) = saved {
+            eprintln!("Error saving the learned mask for {} -> {e:?}", self.url);
+        }
+
+        println!("Learned a mask for {} over {} runs", self.url, self.auto_mask_runs);
+        self.mask = Some(mask);
+        self.mask_learner = None;
+    }
+
+    // at the end of the warmup the lowest warmup score is the stability, a site that can't even match itself will false alarm
+    pub fn record_warmup_score(&mut self, score: f64) {
+        if !self.in_warmup() {
+            return;
+        }
+
+        self.warmup_scores.push(score);
+        if self.total_runs != self.warmup_runs {
+            return;
+        }
+
+        let stability = self.warmup_scores.iter().copied().fold(1.0, f64::min);
+        self.stats.baseline_stability = Some(stability);
+
+        if stability < self.threshold {
+            eprintln!(
+                "{} looks unstable, warmup captures scored as low as {stability} against each other, lower the threshold or add blur, a mask or remove the moving parts",
+                self.url
+            );
+        } else {
+            println!("{} -> baseline stability={stability}", self.url);
+        }
+    }
+
+    // forgets every capture, the next run starts new baselines without alerting
+    pub fn drop_baselines(&mut self) {
+        self.last_image = None;
+        self.previous_image = None;
+        self.region_images = vec![None; self.regions.len()];
+
+        for page in &mut self.pages {
+            page.last_image = None;
+            page.previous_image = None;
+            page.region_images = vec![None; self.regions.len()];
+        }
+    }
+
+    /// how long until the site should be checked again, zero when it's due
+    pub fn due_in(&self) -> Duration {
+        if self.schedule.is_some() {
+            return match self.next_run {
+                Some(next_run) => (next_run - Utc::now()).to_std().unwrap_or_default(),
+                None => Duration::MAX,
+            };
+        }
+
+        self.next_check.map_or(Duration::ZERO, |next_check| next_check.saturating_duration_since(Instant::now()))
+    }
+
+    /// if the site has an interval or schedule instead of being checked every cycle
+    pub fn is_scheduled(&self) -> bool {
+        self.interval.is_some() || self.schedule.is_some()
+    }
+
+    pub fn check_started(&mut self, rng: &mut impl Rng) {
+        if let Some(interval) = self.interval {
+            let factor = 1.0 + rng.gen_range(-self.jitter..=self.jitter);
+            self.next_check = Some(Instant::now() + Duration::from_secs(interval).mul_f64(factor));
+        }
+
+        if let Some(schedule) = &self.schedule {
+            // only ever later, a schedule shouldn't run before it matches
+            self.next_run = next_occurrence(schedule, self.schedule_timezone, false).map(|next_run| {
+                let until = (next_run - Utc::now()).to_std().unwrap_or_default();
+                next_run + until.mul_f64(rng.gen_range(0.0..=self.jitter))
+            });
+        }
+    }
+
+    /// takes over the state of the site from before a config reload, the baselines only if they're still captured the same way
+    pub fn keep_state(&mut self, old: WebsiteData) {
+        let same_capture = old.urls == self.urls
+            && old.screenshot_selector == self.screenshot_selector
+            && old.canvas_selector == self.canvas_selector
+            && old.device_metrics == self.device_metrics
+            && old.iframe_selector == self.iframe_selector
+            && old.reference_images == self.reference_images
+            && old.transparent_as == self.transparent_as
+            && old.regions.iter().map(|region| &region.selector).eq(self.regions.iter().map(|region| &region.selector));
+
+        if same_capture {
+            self.current_page = old.current_page;
+            self.pages = old.pages;
+            self.last_image = old.last_image;
+            self.previous_image = old.previous_image;
+            self.capture_degraded = old.capture_degraded;
+            self.region_images = old.region_images;
+            self.last_text_hash = old.last_text_hash;
+            self.score_ema = old.score_ema;
+            self.alerted_hashes = old.alerted_hashes;
+        }
+
+        // still learning the same auto mask
+        if self.mask_learner.is_some() && self.auto_mask_runs == old.auto_mask_runs {
+            self.mask_learner = old.mask_learner;
+        }
+
+        if self.interval == old.interval {
+            self.next_check = old.next_check;
+        }
+        if self.schedule == old.schedule && self.schedule_timezone == old.schedule_timezone {
+            self.next_run = old.next_run;
+        }
+        self.last_alert_score = old.last_alert_score;
+        self.merch_already_detected = old.merch_already_detected;
+        self.contains_present = old.contains_present;
+        self.not_contains_present = old.not_contains_present;
+        self.last_text_alert = old.last_text_alert;
+        self.last_presence = old.last_presence;
+        self.cert_valid_to = old.cert_valid_to;
+        self.cert_alerted_for = old.cert_alerted_for;
+        self.change_latched = old.change_latched;
+        self.changes_stacking = old.changes_stacking;
+        self.current_cooldown = old.current_cooldown;
+        self.total_cooldowns = old.total_cooldowns;
+        self.total_runs = old.total_runs;
+        self.warmup_scores = old.warmup_scores;
+        self.unchanged_cycles = old.unchanged_cycles;
+        self.failed_checks = old.failed_checks;
+        self.stats = old.stats;
+    }
+
+    /// what's wrong with the site that building it doesn't catch, only checked by the validate command
+    pub fn problems(&self) -> Vec<String> {
+        let urls = self.urls.iter().chain(&self.notify_url);
+
+        let selectors = [&self.iframe_selector, &self.screenshot_selector, &self.canvas_selector, &self.scroll_until_selector, &self.feed_selector]
+            .into_iter()
+            .flatten()
+            .chain(self.watch_presence.iter().map(|presence| &presence.selector))
+            .chain(self.regions.iter().map(|region| &region.selector))
+            .chain(self.scripts.iter().filter_map(|script| match &script.when {
+                ScriptCondition::IfSelector(selector) => Some(selector),
+                _ => None,
+            }));
+
+        let scripts = self.scripts.iter()
+            .map(|script| &script.code)
+            .chain(&self.decision_script);
+
+        urls.filter_map(|url| validate::url_error(url))
+            .chain(selectors.filter_map(|selector| validate::selector_error(selector)))
+            .chain(scripts.filter_map(|script| validate::script_error(script)))
+            .collect()
+    }
+
+    // stashes the baselines of the current page and brings in the ones of this page
+    pub fn switch_page(&mut self, page: usize) {
+        if page == self.current_page {
+            return;
+        }
+
+        self.pages[self.current_page] = PageBaseline {
+            last_image: self.last_image.take(),
+            previous_image: self.previous_image.take(),
+            capture_degraded: self.capture_degraded,
+            region_images: std::mem::take(&mut self.region_images),
+            last_text_hash: self.last_text_hash,
+            score_ema: self.score_ema,
+        };
+
+        let baseline = std::mem::take(&mut self.pages[page]);
+        self.last_image = baseline.last_image;
+        self.previous_image = baseline.previous_image;
+        self.capture_degraded = baseline.capture_degraded;
+        self.region_images = baseline.region_images;
+        self.last_text_hash = baseline.last_text_hash;
+        self.score_ema = baseline.score_ema;
+        self.current_page = page;
+    }
+
+    // feeds the score into the moving average, returns the new average or none if smoothing is off
+    pub fn update_score_ema(&mut self, score: f64) -> Option<f64> {
+        let alpha = self.score_smoothing?;
+        let ema = match self.score_ema {
+            Some(ema) => alpha * score + (1.0 - alpha) * ema,
+            None => score,
+        };
+
+        self.score_ema = Some(ema);
+        Some(ema)
     }
 
     pub fn nothing_changed(&mut self) {
-        if self.total_cooldowns != 0 {
-            self.total_cooldowns -= 1;
+        match self.cooldown_decay {
+            CooldownDecay::Linear => self.total_cooldowns = self.total_cooldowns.saturating_sub(self.cooldown_decay_step),
+            CooldownDecay::Reset => self.total_cooldowns = 0,
+            CooldownDecay::None => {}
         }
 
         self.changes_stacking = 0;
+        self.unchanged_cycles += 1;
+        // a quiet cycle ends the run of changes the min score delta compares against
+        self.last_alert_score = None;
+    }
+
+    // days left on the certificate, once per certificate, when it's within cert expiry days of expiring
+    pub fn cert_expiring(&mut self) -> Option<u64> {
+        let days = self.cert_expiry_days?;
+        let valid_to = self.cert_valid_to?;
+
+        let days_left = valid_to.saturating_sub(unix_now()) / (60 * 60 * 24);
+        if days_left > days || self.cert_alerted_for == Some(valid_to) {
+            return None;
+        }
+
+        self.cert_alerted_for = Some(valid_to);
+        Some(days_left)
+    }
+
+    // the warn if unchanged for cycles, once, when the site went that many cycles in a row without a change
+    pub fn unchanged_too_long(&self) -> Option<u64> {
+        self.warn_if_unchanged_for.filter(|cycles| self.unchanged_cycles == *cycles)
+    }
+
+    // counts a check that errored, returns how many failed in a row when it's time to alert about it
+    // alerts at alert_after failures, then every cooldown failures after that (never again with 0)
+    pub fn check_failed(&mut self, alert_after: u64, cooldown: u64) -> Option<u64> {
+        self.failed_checks += 1;
+
+        let since_alert = self.failed_checks.checked_sub(alert_after)?;
+        let due = match cooldown {
+            0 => since_alert == 0,
+            cooldown => since_alert % cooldown == 0,
+        };
+
+        due.then_some(self.failed_checks)
+    }
+
+    pub fn check_succeeded(&mut self) {
+        self.failed_checks = 0;
     }
 
     // should run init request, basically check if its on a cooldown
@@ -167,13 +1626,20 @@ impl WebsiteData {
     }
 
     // INFERS CHANGES ARE DETECTED, if they are then calculate if this notification should result in a cooldown instead
-    pub fn should_send_notification(&mut self) -> bool {
+    pub fn should_send_notification(&mut self, silenced: bool) -> bool {
+        self.unchanged_cycles = 0;
+
+        // nothing goes out while silenced, so those changes don't stack toward a cooldown either
+        if silenced {
+            return false;
+        }
+
         self.changes_stacking += 1;
-        let banned = self.changes_stacking >= 4;
+        let banned = self.changes_stacking >= COOLDOWN_AFTER_CHANGES;
 
         if banned {
             self.total_cooldowns += 1;
-            self.current_cooldown = (3_u16).pow(self.total_cooldowns);
+            self.current_cooldown = COOLDOWN_BASE.saturating_pow(self.total_cooldowns);
             self.changes_stacking = 0;
 
             println!("Cooldown given for {} for {} cycles, stacked cooldowns={}", self.url, self.current_cooldown, self.total_cooldowns);
@@ -181,4 +1647,110 @@ impl WebsiteData {
 
         !banned
     }
-}
\ No newline at end of file
+}
+
+// removes everything the patterns match, so clocks/counters don't count as changes
+fn strip_patterns<'a>(patterns: &[Regex], text: &'a str) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for pattern in patterns {
+        if let Cow::Owned(stripped) = pattern.replace_all(&text, "") {
+            text = Cow::Owned(stripped);
+        }
+    }
+
+    text
+}
+
+fn hash_text(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(config: &str) -> WebsiteData {
+        let config: WebsiteDataConfig = toml::from_str(config).unwrap();
+        config.build(&std::env::temp_dir().join("website-change-notifier-tests"), &[])
+    }
+
+    #[test]
+    fn quiet_cycle_clears_last_alert_score() {
+        let mut site = site("url = \"https://example.com\"\nmin_score_delta = 0.1");
+
+        site.alerted_at(0.4);
+        assert!(!site.far_enough_from_last_alert(0.8));
+        assert!(site.far_enough_from_last_alert(0.3));
+
+        site.nothing_changed();
+        assert!(site.far_enough_from_last_alert(0.8));
+    }
+
+    #[test]
+    fn clearing_the_latch_clears_last_alert_score() {
+        let mut site = site("url = \"https://example.com\"\nmin_score_delta = 0.1\nthreshold = 0.9\nclear_threshold = 0.95");
+
+        assert!(site.latch_change(true, 0.4));
+        site.alerted_at(0.4);
+        assert!(!site.latch_change(false, 0.97));
+        assert!(site.far_enough_from_last_alert(0.8));
+    }
+
+    #[test]
+    fn stacked_cooldowns_saturate() {
+        let mut site = site("url = \"https://example.com\"\ncooldown_decay = \"none\"");
+
+        for _ in 0..COOLDOWN_AFTER_CHANGES as u32 * 20 {
+            site.should_send_notification(false);
+        }
+
+        assert_eq!(site.cooldown_left(), u16::MAX);
+    }
+
+    #[test]
+    fn silenced_changes_dont_stack_toward_a_cooldown() {
+        let mut site = site("url = \"https://example.com\"");
+
+        for _ in 0..COOLDOWN_AFTER_CHANGES * 2 {
+            assert!(!site.should_send_notification(true));
+        }
+
+        assert_eq!(site.cooldown_left(), 0);
+        assert!(site.should_send_notification(false));
+    }
+
+    #[test]
+    fn text_alert_held_by_the_cooldown_fires_later() {
+        let mut site = site("url = \"https://example.com\"\nalert_if_contains = [\"in stock\"]\ntext_alert_cooldown = 3");
+
+        site.run();
+        assert_eq!(site.text_alerts("In Stock"), vec!["now contains \"in stock\""]);
+        site.record_text("In Stock", true);
+
+        site.run();
+        site.record_text("sold out", false);
+        site.run();
+        assert!(!site.text_alerts("In Stock").is_empty());
+        assert!(site.text_alert_on_cooldown());
+
+        site.run();
+        assert!(!site.text_alerts("In Stock").is_empty());
+        assert!(!site.text_alert_on_cooldown());
+    }
+
+    #[test]
+    fn feed_forgets_the_least_recently_seen_items() {
+        let mut site = site("url = \"https://example.com\"\nfeed = \"li\"");
+        assert_eq!(site.feed_selector().as_deref(), Some("li"));
+        let items = |range: std::ops::Range<usize>| range.map(|i| format!("item {i}")).collect::<Vec<String>>();
+
+        assert_eq!(site.new_feed_items(items(0..10)), None);
+        assert_eq!(site.new_feed_items(items(5..SEEN_ITEMS_LIMIT + 5)).map(|new| new.len()), Some(SEEN_ITEMS_LIMIT - 5));
+        assert_eq!(site.stats.seen_items.as_ref().map(VecDeque::len), Some(SEEN_ITEMS_LIMIT));
+
+        // 5 to 9 were seen again so they're kept, 0 to 4 got forgotten
+        assert_eq!(site.new_feed_items(items(0..10)), Some(items(0..5)));
+    }
+}